@@ -0,0 +1,1107 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// the congestion window limits how much data can be in flight on top of the
+/// peer's window, all sizes are in bytes
+pub trait CongestionControl: Send {
+    fn cwnd(&self) -> u32;
+
+    fn ssthresh(&self) -> u32;
+
+    /// `acked` new bytes were acknowledged outside of fast recovery
+    fn on_ack(&mut self, acked: u32);
+
+    /// three duplicate ACKs signalled a loss with `flight_size` bytes
+    /// outstanding, the window after recovery is `cwnd()`
+    fn on_loss(&mut self, flight_size: u32);
+
+    /// the retransmission timer expired with `flight_size` bytes outstanding
+    fn on_rto(&mut self, flight_size: u32);
+
+    /// the retransmissions after the last `on_loss` or `on_rto` were spurious,
+    /// restore the state from before the reduction (RFC 4015)
+    fn undo(&mut self);
+
+    /// round-trip time measured from a segment that wasn't retransmitted
+    fn on_rtt_sample(&mut self, _rtt: Duration) {}
+
+    /// delivery rate measured when new data was acknowledged, called before `on_ack`
+    fn on_rate_sample(&mut self, _sample: &RateSample) {}
+
+    /// bytes per second to pace transmissions at, `None` sends whatever the
+    /// windows allow at once
+    fn pacing_rate(&self) -> Option<f64> {
+        None
+    }
+}
+
+/// delivery rate sample (draft-cheng-iccrg-delivery-rate-estimation)
+pub struct RateSample {
+    /// bytes delivered over the sampling interval
+    pub delivered: u64,
+    pub interval: Duration,
+    /// bytes delivered on the connection when the sampled segment was sent
+    pub prior_delivered: u64,
+    /// bytes delivered on the connection so far
+    pub total_delivered: u64,
+    /// the sender ran out of data during the interval, so the rate is only a
+    /// lower bound of what the path can do
+    pub app_limited: bool,
+    /// bytes still in flight after the ACK
+    pub in_flight: u32,
+}
+
+impl RateSample {
+    /// bytes per second
+    pub fn delivery_rate(&self) -> f64 {
+        self.delivered as f64 / self.interval.as_secs_f64()
+    }
+}
+
+/// congestion control algorithms a socket can use
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CongestionAlgorithm {
+    #[default]
+    NewReno,
+    Cubic,
+    Bbr,
+}
+
+impl CongestionAlgorithm {
+    pub fn build(self, mss: u16) -> Box<dyn CongestionControl> {
+        match self {
+            CongestionAlgorithm::NewReno => Box::new(NewReno::new(mss)),
+            CongestionAlgorithm::Cubic => Box::new(Cubic::new(mss)),
+            CongestionAlgorithm::Bbr => Box::new(Bbr::new(mss)),
+        }
+    }
+}
+
+/// RFC 5681 3.1
+fn initial_window(mss: u32) -> u32 {
+    match mss {
+        0..=1095 => 4 * mss,
+        1096..=2190 => 3 * mss,
+        _ => 2 * mss,
+    }
+}
+
+/// NewReno congestion control (RFC 5681, RFC 6582)
+pub struct NewReno {
+    mss: u32,
+    cwnd: u32,
+    ssthresh: u32,
+    /// bytes acknowledged since the window last grew in congestion avoidance,
+    /// growing by one MSS per window of data acked (RFC 3465)
+    bytes_acked: u32,
+    /// cwnd and ssthresh before the last reduction
+    prior: Option<(u32, u32)>,
+}
+
+impl NewReno {
+    pub fn new(mss: u16) -> Self {
+        let mss = mss as u32;
+
+        Self {
+            mss,
+            cwnd: initial_window(mss),
+            // arbitrarily high, slow start runs until the first loss (RFC 5681 3.1)
+            ssthresh: u32::MAX,
+            bytes_acked: 0,
+            prior: None,
+        }
+    }
+}
+
+impl CongestionControl for NewReno {
+    fn cwnd(&self) -> u32 {
+        self.cwnd
+    }
+
+    fn ssthresh(&self) -> u32 {
+        self.ssthresh
+    }
+
+    fn on_ack(&mut self, acked: u32) {
+        if self.cwnd < self.ssthresh {
+            // slow start, at most one MSS per ACK so stretch ACKs can't cause bursts
+            self.cwnd = self.cwnd.saturating_add(acked.min(self.mss));
+        } else {
+            self.bytes_acked = self.bytes_acked.saturating_add(acked);
+            if self.bytes_acked >= self.cwnd {
+                self.bytes_acked -= self.cwnd;
+                self.cwnd = self.cwnd.saturating_add(self.mss);
+            }
+        }
+    }
+
+    fn on_loss(&mut self, flight_size: u32) {
+        self.prior = Some((self.cwnd, self.ssthresh));
+        self.ssthresh = (flight_size / 2).max(2 * self.mss);
+        self.cwnd = self.ssthresh;
+        self.bytes_acked = 0;
+    }
+
+    /// the window collapses to one segment and slow start begins again
+    fn on_rto(&mut self, flight_size: u32) {
+        self.prior = Some((self.cwnd, self.ssthresh));
+        self.ssthresh = (flight_size / 2).max(2 * self.mss);
+        self.cwnd = self.mss;
+        self.bytes_acked = 0;
+    }
+
+    fn undo(&mut self) {
+        if let Some((cwnd, ssthresh)) = self.prior.take() {
+            self.cwnd = self.cwnd.max(cwnd);
+            self.ssthresh = self.ssthresh.max(ssthresh);
+        }
+    }
+}
+
+/// CUBIC congestion control (RFC 9438), the window grows as a cubic function
+/// of the time since the last loss, so it recovers quickly on paths with a
+/// large bandwidth-delay product
+pub struct Cubic {
+    mss: u32,
+    /// kept fractional so per-ACK growth smaller than a byte isn't lost
+    cwnd: f64,
+    ssthresh: u32,
+    /// W_max, window before the last reduction in segments
+    w_max: f64,
+    /// start of the current congestion avoidance epoch
+    epoch_start: Option<Instant>,
+    /// time it takes the window to grow back to W_max, in seconds
+    k: f64,
+    /// W_est, window NewReno would have in the same situation, in segments
+    w_est: f64,
+    min_rtt: Option<Duration>,
+    /// cwnd, ssthresh and W_max before the last reduction
+    prior: Option<(f64, u32, f64)>,
+}
+
+impl Cubic {
+    const C: f64 = 0.4;
+    const BETA: f64 = 0.7;
+
+    pub fn new(mss: u16) -> Self {
+        let mss = mss as u32;
+
+        Self {
+            mss,
+            cwnd: initial_window(mss) as f64,
+            ssthresh: u32::MAX,
+            w_max: 0.0,
+            epoch_start: None,
+            k: 0.0,
+            w_est: 0.0,
+            min_rtt: None,
+            prior: None,
+        }
+    }
+
+    /// RFC 9438 4.2, W_cubic(t) in segments
+    fn w_cubic(&self, t: f64) -> f64 {
+        Self::C * (t - self.k).powi(3) + self.w_max
+    }
+
+    /// RFC 9438 4.6 and 4.7, multiplicative decrease with fast convergence
+    fn reduce(&mut self) {
+        self.prior = Some((self.cwnd, self.ssthresh, self.w_max));
+        let w = self.cwnd / self.mss as f64;
+
+        // release bandwidth to new flows if the window didn't reach the last maximum
+        self.w_max = if w < self.w_max {
+            w * (1.0 + Self::BETA) / 2.0
+        } else {
+            w
+        };
+        self.ssthresh = ((self.cwnd * Self::BETA) as u32).max(2 * self.mss);
+        self.epoch_start = None;
+    }
+}
+
+impl CongestionControl for Cubic {
+    fn cwnd(&self) -> u32 {
+        self.cwnd as u32
+    }
+
+    fn ssthresh(&self) -> u32 {
+        self.ssthresh
+    }
+
+    fn on_ack(&mut self, acked: u32) {
+        let mss = self.mss as f64;
+
+        if (self.cwnd as u32) < self.ssthresh {
+            self.cwnd += acked.min(self.mss) as f64;
+            return;
+        }
+
+        let now = Instant::now();
+        let w = self.cwnd / mss;
+
+        let epoch_start = match self.epoch_start {
+            Some(epoch_start) => epoch_start,
+            None => {
+                if w < self.w_max {
+                    self.k = ((self.w_max - w) / Self::C).cbrt();
+                } else {
+                    self.k = 0.0;
+                    self.w_max = w;
+                }
+                self.w_est = w;
+                *self.epoch_start.insert(now)
+            }
+        };
+
+        let t = now.duration_since(epoch_start).as_secs_f64();
+        let rtt = self.min_rtt.unwrap_or_default().as_secs_f64();
+
+        // RFC 9438 4.3, grow at least as fast as NewReno would
+        let alpha = if self.w_est >= self.w_max {
+            1.0
+        } else {
+            3.0 * (1.0 - Self::BETA) / (1.0 + Self::BETA)
+        };
+        self.w_est += alpha * (acked as f64 / mss) / w;
+
+        if self.w_cubic(t) < self.w_est {
+            self.cwnd = self.w_est * mss;
+        } else {
+            // RFC 9438 4.4 and 4.5, aim for the window one RTT from now
+            let target = self.w_cubic(t + rtt).clamp(w, 1.5 * w);
+            self.cwnd += (target - w) / w * acked as f64;
+        }
+    }
+
+    fn on_loss(&mut self, _flight_size: u32) {
+        self.reduce();
+        self.cwnd = self.ssthresh as f64;
+    }
+
+    fn on_rto(&mut self, _flight_size: u32) {
+        self.reduce();
+        self.cwnd = self.mss as f64;
+    }
+
+    fn undo(&mut self) {
+        if let Some((cwnd, ssthresh, w_max)) = self.prior.take() {
+            self.cwnd = self.cwnd.max(cwnd);
+            self.ssthresh = self.ssthresh.max(ssthresh);
+            self.w_max = w_max;
+            self.epoch_start = None;
+        }
+    }
+
+    fn on_rtt_sample(&mut self, rtt: Duration) {
+        self.min_rtt = Some(self.min_rtt.map_or(rtt, |min_rtt| min_rtt.min(rtt)));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BbrMode {
+    Startup,
+    Drain,
+    ProbeBw,
+    ProbeRtt,
+}
+
+/// BBR congestion control (draft-cardwell-iccrg-bbr-congestion-control-00),
+/// models the path from its bottleneck bandwidth and round-trip propagation
+/// delay instead of reacting to losses, and paces data at the estimated rate
+pub struct Bbr {
+    mss: u32,
+    cwnd: u32,
+    mode: BbrMode,
+    pacing_gain: f64,
+    cwnd_gain: f64,
+    /// BtlBw, windowed max of the delivery rate in bytes per second
+    btl_bw: f64,
+    /// largest delivery rate seen in each of the last rounds
+    btl_bw_filter: VecDeque<(u64, f64)>,
+    /// RTprop, windowed min of the round-trip time
+    rt_prop: Option<Duration>,
+    rt_prop_stamp: Instant,
+    rt_prop_expired: bool,
+    /// round trips counted in delivered data
+    round_count: u64,
+    next_round_delivered: u64,
+    round_start: bool,
+    total_delivered: u64,
+    in_flight: u32,
+    /// whether startup found the bottleneck bandwidth
+    filled_pipe: bool,
+    full_bw: f64,
+    full_bw_count: u32,
+    cycle_index: usize,
+    cycle_stamp: Instant,
+    /// when ProbeRTT can end, once the window drained to the minimum
+    probe_rtt_done_stamp: Option<Instant>,
+    probe_rtt_round_done: bool,
+    /// window to restore after ProbeRTT
+    prior_cwnd: u32,
+    /// window before the last loss response
+    prior_loss_cwnd: Option<u32>,
+}
+
+impl Bbr {
+    /// 2/ln(2), the smallest gain that doubles the sending rate every round
+    const HIGH_GAIN: f64 = 2.885;
+    const PACING_GAIN_CYCLE: [f64; 8] = [1.25, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+    /// rounds covered by the BtlBw filter
+    const BTL_BW_FILTER_LEN: u64 = 10;
+    const RT_PROP_FILTER_LEN: Duration = Duration::from_secs(10);
+    const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+    const MIN_PIPE_CWND_SEGMENTS: u32 = 4;
+
+    pub fn new(mss: u16) -> Self {
+        let mss = mss as u32;
+        let now = Instant::now();
+
+        Self {
+            mss,
+            cwnd: initial_window(mss),
+            mode: BbrMode::Startup,
+            pacing_gain: Self::HIGH_GAIN,
+            cwnd_gain: Self::HIGH_GAIN,
+            btl_bw: 0.0,
+            btl_bw_filter: VecDeque::new(),
+            rt_prop: None,
+            rt_prop_stamp: now,
+            rt_prop_expired: false,
+            round_count: 0,
+            next_round_delivered: 0,
+            round_start: false,
+            total_delivered: 0,
+            in_flight: 0,
+            filled_pipe: false,
+            full_bw: 0.0,
+            full_bw_count: 0,
+            cycle_index: 0,
+            cycle_stamp: now,
+            probe_rtt_done_stamp: None,
+            probe_rtt_round_done: false,
+            prior_cwnd: 0,
+            prior_loss_cwnd: None,
+        }
+    }
+
+    fn min_pipe_cwnd(&self) -> u32 {
+        Self::MIN_PIPE_CWND_SEGMENTS * self.mss
+    }
+
+    /// estimated bandwidth-delay product scaled by `gain`
+    fn bdp(&self, gain: f64) -> u32 {
+        match self.rt_prop {
+            Some(rt_prop) if self.btl_bw > 0.0 => {
+                (gain * self.btl_bw * rt_prop.as_secs_f64()) as u32
+            }
+            _ => initial_window(self.mss),
+        }
+    }
+
+    fn update_btl_bw(&mut self, sample: &RateSample) {
+        let rate = sample.delivery_rate();
+        // app-limited samples underestimate the bandwidth unless they beat the estimate
+        if sample.app_limited && rate < self.btl_bw {
+            return;
+        }
+
+        match self.btl_bw_filter.back_mut() {
+            Some((round, max)) if *round == self.round_count => *max = max.max(rate),
+            _ => self.btl_bw_filter.push_back((self.round_count, rate)),
+        }
+        while let Some((round, _)) = self.btl_bw_filter.front() {
+            if round + Self::BTL_BW_FILTER_LEN > self.round_count {
+                break;
+            }
+            self.btl_bw_filter.pop_front();
+        }
+
+        self.btl_bw = self
+            .btl_bw_filter
+            .iter()
+            .map(|(_, rate)| *rate)
+            .fold(0.0, f64::max);
+    }
+
+    /// startup is over once the bandwidth stops growing by 25% for three rounds
+    fn check_full_pipe(&mut self, sample: &RateSample) {
+        if self.filled_pipe || !self.round_start || sample.app_limited {
+            return;
+        }
+
+        if self.btl_bw >= self.full_bw * 1.25 {
+            self.full_bw = self.btl_bw;
+            self.full_bw_count = 0;
+            return;
+        }
+
+        self.full_bw_count += 1;
+        if self.full_bw_count >= 3 {
+            self.filled_pipe = true;
+        }
+    }
+
+    fn enter_startup(&mut self) {
+        self.mode = BbrMode::Startup;
+        self.pacing_gain = Self::HIGH_GAIN;
+        self.cwnd_gain = Self::HIGH_GAIN;
+    }
+
+    fn enter_probe_bw(&mut self, now: Instant) {
+        self.mode = BbrMode::ProbeBw;
+        self.cwnd_gain = 2.0;
+        // start at a random phase other than the draining one
+        self.cycle_index = rand::random_range(1..Self::PACING_GAIN_CYCLE.len());
+        self.advance_cycle_phase(now);
+    }
+
+    fn advance_cycle_phase(&mut self, now: Instant) {
+        self.cycle_stamp = now;
+        self.cycle_index = (self.cycle_index + 1) % Self::PACING_GAIN_CYCLE.len();
+        self.pacing_gain = Self::PACING_GAIN_CYCLE[self.cycle_index];
+    }
+
+    fn check_cycle_phase(&mut self, now: Instant) {
+        let rt_prop = self.rt_prop.unwrap_or_default();
+        let full_length = now.duration_since(self.cycle_stamp) > rt_prop;
+
+        let next_phase = if self.pacing_gain > 1.0 {
+            // probe until the extra data is actually in flight
+            full_length && self.in_flight >= self.bdp(self.pacing_gain)
+        } else if self.pacing_gain < 1.0 {
+            // drain the queue created by probing, possibly early
+            full_length || self.in_flight <= self.bdp(1.0)
+        } else {
+            full_length
+        };
+
+        if next_phase {
+            self.advance_cycle_phase(now);
+        }
+    }
+
+    /// drains the queue for a moment to measure RTprop if it wasn't seen for a while
+    fn check_probe_rtt(&mut self, now: Instant) {
+        if self.mode != BbrMode::ProbeRtt && self.rt_prop_expired {
+            self.mode = BbrMode::ProbeRtt;
+            self.pacing_gain = 1.0;
+            self.cwnd_gain = 1.0;
+            self.prior_cwnd = self.cwnd;
+            self.probe_rtt_done_stamp = None;
+        }
+
+        if self.mode != BbrMode::ProbeRtt {
+            return;
+        }
+
+        match self.probe_rtt_done_stamp {
+            None if self.in_flight <= self.min_pipe_cwnd() => {
+                self.probe_rtt_done_stamp = Some(now + Self::PROBE_RTT_DURATION);
+                self.probe_rtt_round_done = false;
+                self.next_round_delivered = self.total_delivered;
+            }
+            None => {}
+            Some(done_stamp) => {
+                if self.round_start {
+                    self.probe_rtt_round_done = true;
+                }
+
+                if self.probe_rtt_round_done && now > done_stamp {
+                    self.rt_prop_stamp = now;
+                    self.rt_prop_expired = false;
+                    self.cwnd = self.cwnd.max(self.prior_cwnd);
+
+                    if self.filled_pipe {
+                        self.enter_probe_bw(now);
+                    } else {
+                        self.enter_startup();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl CongestionControl for Bbr {
+    fn cwnd(&self) -> u32 {
+        if self.mode == BbrMode::ProbeRtt {
+            self.cwnd.min(self.min_pipe_cwnd())
+        } else {
+            self.cwnd
+        }
+    }
+
+    /// BBR doesn't use a slow start threshold
+    fn ssthresh(&self) -> u32 {
+        u32::MAX
+    }
+
+    fn on_ack(&mut self, acked: u32) {
+        let target = self.bdp(self.cwnd_gain) + 3 * self.mss;
+
+        if self.filled_pipe {
+            self.cwnd = self.cwnd.saturating_add(acked).min(target);
+        } else if self.cwnd < target || self.total_delivered < initial_window(self.mss) as u64 {
+            self.cwnd = self.cwnd.saturating_add(acked);
+        }
+
+        self.cwnd = self.cwnd.max(self.min_pipe_cwnd());
+    }
+
+    /// only the segments still in flight may be outstanding during recovery
+    fn on_loss(&mut self, flight_size: u32) {
+        self.prior_loss_cwnd = Some(self.cwnd);
+        self.cwnd = flight_size.max(self.min_pipe_cwnd());
+    }
+
+    fn on_rto(&mut self, _flight_size: u32) {
+        self.prior_loss_cwnd = Some(self.cwnd);
+        self.cwnd = self.mss;
+    }
+
+    /// the model doesn't react to losses, only the window needs restoring
+    fn undo(&mut self) {
+        if let Some(cwnd) = self.prior_loss_cwnd.take() {
+            self.cwnd = self.cwnd.max(cwnd);
+        }
+    }
+
+    fn on_rtt_sample(&mut self, rtt: Duration) {
+        let now = Instant::now();
+
+        self.rt_prop_expired = now.duration_since(self.rt_prop_stamp) > Self::RT_PROP_FILTER_LEN;
+        if self.rt_prop.is_none_or(|rt_prop| rtt <= rt_prop) || self.rt_prop_expired {
+            self.rt_prop = Some(rtt);
+            self.rt_prop_stamp = now;
+        }
+    }
+
+    fn on_rate_sample(&mut self, sample: &RateSample) {
+        let now = Instant::now();
+
+        self.total_delivered = sample.total_delivered;
+        self.in_flight = sample.in_flight;
+
+        self.round_start = sample.prior_delivered >= self.next_round_delivered;
+        if self.round_start {
+            self.next_round_delivered = sample.total_delivered;
+            self.round_count += 1;
+        }
+
+        self.update_btl_bw(sample);
+
+        if self.mode == BbrMode::ProbeBw {
+            self.check_cycle_phase(now);
+        }
+
+        self.check_full_pipe(sample);
+        if self.mode == BbrMode::Startup && self.filled_pipe {
+            self.mode = BbrMode::Drain;
+            self.pacing_gain = 1.0 / Self::HIGH_GAIN;
+            self.cwnd_gain = Self::HIGH_GAIN;
+        }
+        if self.mode == BbrMode::Drain && self.in_flight <= self.bdp(1.0) {
+            self.enter_probe_bw(now);
+        }
+
+        self.check_probe_rtt(now);
+    }
+
+    fn pacing_rate(&self) -> Option<f64> {
+        if self.btl_bw > 0.0 {
+            return Some(self.pacing_gain * self.btl_bw);
+        }
+
+        // no bandwidth estimate yet, pace the initial window over the RTT or 1ms
+        let rtt = self
+            .rt_prop
+            .unwrap_or(Duration::from_millis(1))
+            .as_secs_f64();
+        Some(self.pacing_gain * initial_window(self.mss) as f64 / rtt.max(0.001))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: u32 = 1000;
+
+    #[test]
+    fn initial_window_follows_the_mss() {
+        assert_eq!(initial_window(536), 4 * 536);
+        assert_eq!(initial_window(1460), 3 * 1460);
+        assert_eq!(initial_window(8960), 2 * 8960);
+    }
+
+    #[test]
+    fn new_reno_slow_start_grows_by_one_mss_per_ack() {
+        let mut cc = NewReno::new(MSS as u16);
+        assert_eq!(cc.cwnd(), 4 * MSS);
+
+        cc.on_ack(MSS);
+        assert_eq!(cc.cwnd(), 5 * MSS);
+
+        // stretch ACKs count as one segment
+        cc.on_ack(3 * MSS);
+        assert_eq!(cc.cwnd(), 6 * MSS);
+
+        cc.on_ack(MSS / 2);
+        assert_eq!(cc.cwnd(), 6 * MSS + MSS / 2);
+    }
+
+    #[test]
+    fn new_reno_collapses_on_rto_and_slow_starts() {
+        let mut cc = NewReno::new(MSS as u16);
+        for _ in 0..16 {
+            cc.on_ack(MSS);
+        }
+        assert_eq!(cc.cwnd(), 20 * MSS);
+
+        cc.on_rto(20 * MSS);
+        assert_eq!(cc.ssthresh(), 10 * MSS);
+        assert_eq!(cc.cwnd(), MSS);
+
+        for _ in 0..9 {
+            cc.on_ack(MSS);
+        }
+        assert_eq!(cc.cwnd(), 10 * MSS);
+    }
+
+    #[test]
+    fn new_reno_grows_by_one_mss_per_window_in_congestion_avoidance() {
+        let mut cc = NewReno::new(MSS as u16);
+        cc.on_rto(20 * MSS);
+        for _ in 0..9 {
+            cc.on_ack(MSS);
+        }
+        assert_eq!(cc.cwnd(), cc.ssthresh());
+
+        for _ in 0..9 {
+            cc.on_ack(MSS);
+        }
+        assert_eq!(cc.cwnd(), 10 * MSS);
+        cc.on_ack(MSS);
+        assert_eq!(cc.cwnd(), 11 * MSS);
+
+        for _ in 0..11 {
+            cc.on_ack(MSS);
+        }
+        assert_eq!(cc.cwnd(), 12 * MSS);
+    }
+
+    #[test]
+    fn new_reno_keeps_two_segments_after_rto() {
+        let mut cc = NewReno::new(MSS as u16);
+
+        cc.on_rto(MSS);
+        assert_eq!(cc.ssthresh(), 2 * MSS);
+        assert_eq!(cc.cwnd(), MSS);
+    }
+
+    #[test]
+    fn new_reno_halves_on_loss() {
+        let mut cc = NewReno::new(MSS as u16);
+        for _ in 0..16 {
+            cc.on_ack(MSS);
+        }
+
+        cc.on_loss(20 * MSS);
+        assert_eq!(cc.ssthresh(), 10 * MSS);
+        assert_eq!(cc.cwnd(), 10 * MSS);
+
+        // recovery ends in congestion avoidance
+        for _ in 0..10 {
+            cc.on_ack(MSS);
+        }
+        assert_eq!(cc.cwnd(), 11 * MSS);
+    }
+
+    #[test]
+    fn new_reno_keeps_two_segments_after_loss() {
+        let mut cc = NewReno::new(MSS as u16);
+
+        cc.on_loss(MSS);
+        assert_eq!(cc.ssthresh(), 2 * MSS);
+        assert_eq!(cc.cwnd(), 2 * MSS);
+    }
+
+    #[test]
+    fn cubic_slow_starts_like_new_reno() {
+        let mut cc = Cubic::new(MSS as u16);
+        assert_eq!(cc.cwnd(), 4 * MSS);
+
+        cc.on_ack(MSS);
+        cc.on_ack(3 * MSS);
+        assert_eq!(cc.cwnd(), 6 * MSS);
+        assert!(cc.epoch_start.is_none());
+    }
+
+    #[test]
+    fn cubic_reduces_by_beta() {
+        let mut cc = Cubic::new(MSS as u16);
+        for _ in 0..96 {
+            cc.on_ack(MSS);
+        }
+        assert_eq!(cc.cwnd(), 100 * MSS);
+
+        cc.on_loss(100 * MSS);
+        assert_eq!(cc.ssthresh(), 70 * MSS);
+        assert_eq!(cc.cwnd(), 70 * MSS);
+        assert_eq!(cc.w_max, 100.0);
+    }
+
+    #[test]
+    fn cubic_fast_convergence_lowers_w_max() {
+        let mut cc = Cubic::new(MSS as u16);
+        for _ in 0..96 {
+            cc.on_ack(MSS);
+        }
+
+        cc.on_loss(100 * MSS);
+        // a second loss before the window got back to W_max
+        cc.on_loss(70 * MSS);
+        assert_eq!(cc.w_max, 70.0 * (1.0 + Cubic::BETA) / 2.0);
+        assert_eq!(cc.ssthresh(), 49 * MSS);
+    }
+
+    #[test]
+    fn cubic_grows_slowly_below_w_max() {
+        let mut cc = Cubic::new(MSS as u16);
+        for _ in 0..96 {
+            cc.on_ack(MSS);
+        }
+        cc.on_loss(100 * MSS);
+
+        // right after the loss the curve is flat, growth is bounded by the
+        // NewReno-friendly estimate
+        let mut last = cc.cwnd();
+        for _ in 0..70 {
+            cc.on_ack(MSS);
+            assert!(cc.cwnd() >= last);
+            last = cc.cwnd();
+        }
+        assert!(cc.cwnd() > 70 * MSS);
+        assert!(cc.cwnd() < 72 * MSS);
+        assert!(cc.epoch_start.is_some());
+        assert!(cc.k > 0.0);
+    }
+
+    #[test]
+    fn cubic_curve_returns_to_w_max_after_k() {
+        let mut cc = Cubic::new(MSS as u16);
+        cc.w_max = 100.0;
+        cc.k = ((100.0 - 70.0) / Cubic::C).cbrt();
+
+        assert!((cc.w_cubic(0.0) - 70.0).abs() < 1e-9);
+        assert!((cc.w_cubic(cc.k) - 100.0).abs() < 1e-9);
+        assert!(cc.w_cubic(2.0 * cc.k) > 100.0);
+    }
+
+    #[test]
+    fn cubic_collapses_on_rto() {
+        let mut cc = Cubic::new(MSS as u16);
+        for _ in 0..96 {
+            cc.on_ack(MSS);
+        }
+
+        cc.on_rto(100 * MSS);
+        assert_eq!(cc.cwnd(), MSS);
+        assert_eq!(cc.ssthresh(), 70 * MSS);
+        assert!(cc.epoch_start.is_none());
+    }
+
+    #[test]
+    fn new_reno_undo_restores_the_window() {
+        let mut cc = NewReno::new(MSS as u16);
+        for _ in 0..16 {
+            cc.on_ack(MSS);
+        }
+
+        cc.on_rto(20 * MSS);
+        cc.undo();
+        assert_eq!(cc.cwnd(), 20 * MSS);
+        assert_eq!(cc.ssthresh(), u32::MAX);
+
+        // only the last reduction can be undone
+        cc.on_loss(20 * MSS);
+        cc.undo();
+        cc.undo();
+        assert_eq!(cc.cwnd(), 20 * MSS);
+    }
+
+    #[test]
+    fn cubic_undo_restores_the_window_and_w_max() {
+        let mut cc = Cubic::new(MSS as u16);
+        for _ in 0..96 {
+            cc.on_ack(MSS);
+        }
+        cc.on_loss(100 * MSS);
+        for _ in 0..10 {
+            cc.on_ack(MSS);
+        }
+
+        cc.on_rto(70 * MSS);
+        cc.undo();
+        assert!(cc.cwnd() >= 70 * MSS);
+        assert_eq!(cc.ssthresh(), 70 * MSS);
+        assert_eq!(cc.w_max, 100.0);
+        // the next epoch starts from the restored window
+        assert!(cc.epoch_start.is_none());
+    }
+
+    /// feeds BBR the ACKs of one round trip, delivering `rate` bytes per
+    /// second with `in_flight` bytes left outstanding
+    fn bbr_round(bbr: &mut Bbr, rate: u64, in_flight: u32) {
+        bbr.on_rate_sample(&RateSample {
+            delivered: rate,
+            interval: Duration::from_secs(1),
+            prior_delivered: bbr.next_round_delivered,
+            total_delivered: bbr.total_delivered + rate,
+            app_limited: false,
+            in_flight,
+        });
+    }
+
+    #[test]
+    fn bbr_leaves_startup_once_the_bandwidth_stops_growing() {
+        let mut bbr = Bbr::new(MSS as u16);
+        bbr.on_rtt_sample(Duration::from_millis(10));
+
+        for rate in [1_000_000, 2_000_000, 4_000_000, 4_000_000, 4_500_000] {
+            bbr_round(&mut bbr, rate, 100 * MSS);
+            assert_eq!(bbr.mode, BbrMode::Startup);
+            assert_eq!(bbr.pacing_gain, Bbr::HIGH_GAIN);
+        }
+
+        // the third round without 25% growth fills the pipe
+        bbr_round(&mut bbr, 4_000_000, 100 * MSS);
+        assert!(bbr.filled_pipe);
+        assert_eq!(bbr.mode, BbrMode::Drain);
+        assert_eq!(bbr.pacing_gain, 1.0 / Bbr::HIGH_GAIN);
+        assert_eq!(bbr.btl_bw, 4_500_000.0);
+
+        // draining goes on until the queue is gone
+        let in_flight = bbr.bdp(1.0) + 1;
+        bbr_round(&mut bbr, 4_000_000, in_flight);
+        assert_eq!(bbr.mode, BbrMode::Drain);
+        let in_flight = bbr.bdp(1.0);
+        bbr_round(&mut bbr, 4_000_000, in_flight);
+        assert_eq!(bbr.mode, BbrMode::ProbeBw);
+        assert_eq!(bbr.cwnd_gain, 2.0);
+        // never starts in the draining phase
+        assert_ne!(bbr.pacing_gain, 0.75);
+    }
+
+    #[test]
+    fn bbr_startup_doubles_the_window_per_round() {
+        let mut bbr = Bbr::new(MSS as u16);
+        bbr.on_rtt_sample(Duration::from_millis(10));
+        bbr_round(&mut bbr, 1_000_000, 4 * MSS);
+
+        bbr.on_ack(4 * MSS);
+        assert_eq!(bbr.cwnd(), 8 * MSS);
+        // up to the target of the high gain
+        for _ in 0..10 {
+            bbr.on_ack(8 * MSS);
+        }
+        assert!(bbr.cwnd() >= bbr.bdp(Bbr::HIGH_GAIN) + 3 * MSS);
+        let cwnd = bbr.cwnd();
+        bbr.on_ack(8 * MSS);
+        assert_eq!(bbr.cwnd(), cwnd);
+    }
+
+    #[test]
+    fn bbr_caps_the_window_at_twice_the_bdp_once_the_pipe_is_full() {
+        let mut bbr = Bbr::new(MSS as u16);
+        bbr.on_rtt_sample(Duration::from_millis(10));
+        for _ in 0..4 {
+            bbr_round(&mut bbr, 1_000_000, 0);
+        }
+        assert_eq!(bbr.mode, BbrMode::ProbeBw);
+
+        for _ in 0..100 {
+            bbr.on_ack(MSS);
+        }
+        assert_eq!(bbr.cwnd(), bbr.bdp(2.0) + 3 * MSS);
+        assert_eq!(bbr.bdp(1.0), 10 * MSS);
+    }
+
+    #[test]
+    fn bbr_bandwidth_filter_covers_ten_rounds() {
+        let mut bbr = Bbr::new(MSS as u16);
+
+        bbr_round(&mut bbr, 2_000_000, 0);
+        for _ in 0..Bbr::BTL_BW_FILTER_LEN - 1 {
+            bbr_round(&mut bbr, 1_000_000, 0);
+            assert_eq!(bbr.btl_bw, 2_000_000.0);
+        }
+
+        bbr_round(&mut bbr, 1_000_000, 0);
+        assert_eq!(bbr.btl_bw, 1_000_000.0);
+    }
+
+    #[test]
+    fn bbr_bandwidth_filter_keeps_the_max_of_a_round() {
+        let mut bbr = Bbr::new(MSS as u16);
+        bbr_round(&mut bbr, 1_000_000, 0);
+
+        // a sample for data sent before the round started
+        bbr.on_rate_sample(&RateSample {
+            delivered: 3_000_000,
+            interval: Duration::from_secs(1),
+            prior_delivered: bbr.next_round_delivered - 1,
+            total_delivered: bbr.total_delivered + 3_000_000,
+            app_limited: false,
+            in_flight: 0,
+        });
+        assert!(!bbr.round_start);
+        assert_eq!(bbr.round_count, 1);
+        assert_eq!(bbr.btl_bw_filter.len(), 1);
+        assert_eq!(bbr.btl_bw, 3_000_000.0);
+    }
+
+    #[test]
+    fn bbr_ignores_app_limited_samples_below_the_estimate() {
+        let mut bbr = Bbr::new(MSS as u16);
+        bbr_round(&mut bbr, 2_000_000, 0);
+
+        let mut sample = RateSample {
+            delivered: 1_000_000,
+            interval: Duration::from_secs(1),
+            prior_delivered: bbr.next_round_delivered,
+            total_delivered: bbr.total_delivered + 1_000_000,
+            app_limited: true,
+            in_flight: 0,
+        };
+        bbr.on_rate_sample(&sample);
+        assert_eq!(bbr.btl_bw, 2_000_000.0);
+        assert_eq!(bbr.btl_bw_filter.len(), 1);
+
+        // unless they show more bandwidth than estimated
+        sample.delivered = 3_000_000;
+        sample.prior_delivered = bbr.next_round_delivered;
+        sample.total_delivered = bbr.total_delivered + 3_000_000;
+        bbr.on_rate_sample(&sample);
+        assert_eq!(bbr.btl_bw, 3_000_000.0);
+    }
+
+    #[test]
+    fn bbr_probes_rtt_once_rt_prop_expires() {
+        let mut bbr = Bbr::new(MSS as u16);
+        bbr.on_rtt_sample(Duration::from_millis(10));
+        for _ in 0..4 {
+            bbr_round(&mut bbr, 10_000_000, 0);
+        }
+        for _ in 0..100 {
+            bbr.on_ack(MSS);
+        }
+        let cwnd = bbr.cwnd();
+        assert!(cwnd > 4 * MSS);
+
+        // RTprop wasn't confirmed for longer than the filter window
+        bbr.rt_prop_stamp -= Bbr::RT_PROP_FILTER_LEN + Duration::from_secs(1);
+        bbr.on_rtt_sample(Duration::from_millis(20));
+        assert!(bbr.rt_prop_expired);
+        assert_eq!(bbr.rt_prop, Some(Duration::from_millis(20)));
+
+        bbr_round(&mut bbr, 10_000_000, 20 * MSS);
+        assert_eq!(bbr.mode, BbrMode::ProbeRtt);
+        assert_eq!(bbr.pacing_gain, 1.0);
+        assert_eq!(bbr.cwnd(), 4 * MSS);
+        // the timer only starts once the flight drained to the minimum
+        assert!(bbr.probe_rtt_done_stamp.is_none());
+
+        bbr_round(&mut bbr, 10_000_000, 4 * MSS);
+        assert!(bbr.probe_rtt_done_stamp.is_some());
+        bbr_round(&mut bbr, 10_000_000, 4 * MSS);
+        assert_eq!(bbr.mode, BbrMode::ProbeRtt);
+
+        // and a round trip past PROBE_RTT_DURATION ends it
+        bbr.probe_rtt_done_stamp = Some(Instant::now() - Duration::from_millis(1));
+        bbr_round(&mut bbr, 10_000_000, 4 * MSS);
+        assert_eq!(bbr.mode, BbrMode::ProbeBw);
+        assert!(!bbr.rt_prop_expired);
+        assert_eq!(bbr.cwnd(), cwnd);
+    }
+
+    #[test]
+    fn bbr_returns_to_startup_after_probing_rtt_early() {
+        let mut bbr = Bbr::new(MSS as u16);
+        bbr.on_rtt_sample(Duration::from_millis(10));
+        bbr.rt_prop_stamp -= Bbr::RT_PROP_FILTER_LEN + Duration::from_secs(1);
+        bbr.on_rtt_sample(Duration::from_millis(10));
+
+        bbr_round(&mut bbr, 1_000_000, 0);
+        assert_eq!(bbr.mode, BbrMode::ProbeRtt);
+
+        bbr.probe_rtt_done_stamp = Some(Instant::now() - Duration::from_millis(1));
+        bbr_round(&mut bbr, 2_000_000, 0);
+        assert_eq!(bbr.mode, BbrMode::Startup);
+        assert_eq!(bbr.pacing_gain, Bbr::HIGH_GAIN);
+    }
+
+    #[test]
+    fn bbr_probe_bw_cycles_through_the_gains() {
+        let mut bbr = Bbr::new(MSS as u16);
+        bbr.on_rtt_sample(Duration::from_millis(10));
+        for _ in 0..4 {
+            bbr_round(&mut bbr, 1_000_000, 0);
+        }
+        assert_eq!(bbr.mode, BbrMode::ProbeBw);
+
+        bbr.cycle_index = 0;
+        bbr.pacing_gain = Bbr::PACING_GAIN_CYCLE[0];
+        bbr.cycle_stamp = Instant::now();
+
+        // probing lasts until the extra data is in flight
+        let in_flight = bbr.bdp(1.0);
+        bbr_round(&mut bbr, 1_000_000, in_flight);
+        assert_eq!(bbr.pacing_gain, 1.25);
+        bbr.cycle_stamp -= Duration::from_millis(20);
+        let in_flight = bbr.bdp(1.25);
+        bbr_round(&mut bbr, 1_000_000, in_flight);
+        assert_eq!(bbr.pacing_gain, 0.75);
+
+        // draining ends early once the queue is gone
+        let in_flight = bbr.bdp(1.0);
+        bbr_round(&mut bbr, 1_000_000, in_flight);
+        assert_eq!(bbr.cycle_index, 2);
+        assert_eq!(bbr.pacing_gain, 1.0);
+    }
+
+    #[test]
+    fn bbr_paces_at_the_bandwidth_estimate() {
+        let mut bbr = Bbr::new(MSS as u16);
+        let iw = initial_window(MSS) as f64;
+
+        // the initial window over 1ms, or over the RTT once there is one
+        assert_eq!(bbr.pacing_rate(), Some(Bbr::HIGH_GAIN * iw / 0.001));
+        bbr.on_rtt_sample(Duration::from_millis(100));
+        assert_eq!(bbr.pacing_rate(), Some(Bbr::HIGH_GAIN * iw / 0.1));
+
+        bbr_round(&mut bbr, 1_000_000, 0);
+        assert_eq!(bbr.pacing_rate(), Some(Bbr::HIGH_GAIN * 1_000_000.0));
+    }
+
+    #[test]
+    fn bbr_undo_restores_the_window_after_loss() {
+        let mut bbr = Bbr::new(MSS as u16);
+        for _ in 0..10 {
+            bbr.on_ack(MSS);
+        }
+        assert_eq!(bbr.cwnd(), 14 * MSS);
+
+        bbr.on_loss(2 * MSS);
+        assert_eq!(bbr.cwnd(), 4 * MSS);
+        bbr.undo();
+        assert_eq!(bbr.cwnd(), 14 * MSS);
+
+        bbr.on_rto(14 * MSS);
+        assert_eq!(bbr.cwnd(), MSS);
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    time::{Duration, Instant},
+};
+
+use crate::tcp::Quad;
+
+/// picks the initial sequence number of new connections
+pub trait IsnGenerator: Send + Sync {
+    fn isn(&self, quad: &Quad) -> u32;
+}
+
+/// fixed or scripted ISNs, mostly for tests
+impl<F: Fn(&Quad) -> u32 + Send + Sync> IsnGenerator for F {
+    fn isn(&self, quad: &Quad) -> u32 {
+        self(quad)
+    }
+}
+
+/// RFC 6528 3, ISN = M + F(quad, secret), M being a 4 µs timer, so every
+/// quad gets its own sequence space that keeps moving forward across
+/// incarnations of the connection, while other quads can't be guessed from it
+pub struct KeyedIsnGenerator<S = RandomState> {
+    /// keyed hash of the quad, SipHash with random secrets by default
+    key: S,
+    clock: Instant,
+}
+
+impl Default for KeyedIsnGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyedIsnGenerator {
+    pub fn new() -> Self {
+        Self::with_key(RandomState::new())
+    }
+}
+
+impl<S: BuildHasher> KeyedIsnGenerator<S> {
+    /// uses `key` as the secret, a fixed one gives reproducible ISNs
+    pub fn with_key(key: S) -> Self {
+        Self {
+            key,
+            clock: Instant::now(),
+        }
+    }
+
+    /// the ISN for `quad` once `elapsed` has passed on the clock
+    fn isn_at(&self, quad: &Quad, elapsed: Duration) -> u32 {
+        let ticks = (elapsed.as_micros() / 4) as u32;
+        ticks.wrapping_add(self.key.hash_one(quad) as u32)
+    }
+}
+
+impl<S: BuildHasher + Send + Sync> IsnGenerator for KeyedIsnGenerator<S> {
+    fn isn(&self, quad: &Quad) -> u32 {
+        self.isn_at(quad, self.clock.elapsed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcp::seq_lt;
+    use std::{
+        hash::{BuildHasherDefault, DefaultHasher},
+        net::SocketAddrV4,
+    };
+
+    fn quad(port: u16) -> Quad {
+        (
+            "10.0.0.1:9000".parse().unwrap(),
+            SocketAddrV4::new("10.0.0.2".parse().unwrap(), port),
+        )
+    }
+
+    fn generator() -> KeyedIsnGenerator<BuildHasherDefault<DefaultHasher>> {
+        KeyedIsnGenerator::with_key(BuildHasherDefault::default())
+    }
+
+    #[test]
+    fn advances_with_the_clock() {
+        let isn = generator();
+        let quad = quad(40000);
+
+        let mut last = isn.isn_at(&quad, Duration::ZERO);
+        for ms in 1..1000 {
+            let next = isn.isn_at(&quad, Duration::from_millis(ms));
+            assert!(seq_lt(last, next));
+            assert_eq!(next.wrapping_sub(last), 250);
+            last = next;
+        }
+    }
+
+    #[test]
+    fn stays_monotonic_across_the_wrap() {
+        let isn = generator();
+        let quad = quad(40000);
+
+        // 2^32 ticks of 4 µs take about 4.8 hours
+        let wrap = Duration::from_micros(4 << 32);
+        let before = isn.isn_at(&quad, wrap - Duration::from_millis(1));
+        let after = isn.isn_at(&quad, wrap + Duration::from_millis(1));
+        assert!(seq_lt(before, after));
+        assert_eq!(after.wrapping_sub(before), 500);
+    }
+
+    #[test]
+    fn is_deterministic_with_a_fixed_key() {
+        let quad = quad(40000);
+        let elapsed = Duration::from_secs(3);
+
+        assert_eq!(
+            generator().isn_at(&quad, elapsed),
+            generator().isn_at(&quad, elapsed)
+        );
+    }
+
+    #[test]
+    fn quads_get_their_own_offsets() {
+        let isn = generator();
+
+        assert_ne!(
+            isn.isn_at(&quad(40000), Duration::ZERO),
+            isn.isn_at(&quad(40001), Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn different_keys_give_different_offsets() {
+        let quad = quad(40000);
+
+        assert_ne!(
+            KeyedIsnGenerator::new().isn_at(&quad, Duration::ZERO),
+            KeyedIsnGenerator::new().isn_at(&quad, Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn closures_can_be_injected() {
+        let isn: Box<dyn IsnGenerator> = Box::new(|quad: &Quad| quad.1.port() as u32);
+
+        assert_eq!(isn.isn(&quad(40000)), 40000);
+    }
+}
@@ -1,6 +1,10 @@
 #[macro_use]
 extern crate nix;
 
+pub mod congestion;
+pub mod isn;
+pub mod sack;
+pub mod syncookie;
 pub mod tcp;
 pub mod tun;
 pub mod util;
@@ -57,7 +57,7 @@ fn main() {
             "GET",
             path,
             "HTTP/1.0",
-            url.split(':').nth(0).unwrap(),
+            url.split(':').next().unwrap(),
             "curl/8.13.0",
             "*/*"
         );
@@ -71,8 +71,9 @@ fn main() {
             reader.read_line(&mut buf).unwrap();
             info!("Got header: {buf:?}");
             if buf.starts_with("Content-Length:") {
-                content_length =
-                    u64::from_str_radix(&buf["Content-Length: ".len()..buf.len() - 2], 10).unwrap();
+                content_length = buf["Content-Length: ".len()..buf.len() - 2]
+                    .parse()
+                    .unwrap();
             }
         }
 
@@ -0,0 +1,319 @@
+use crate::tcp::{seq_le, seq_lt};
+
+/// sequence ranges the peer reported holding with SACK options, used to only
+/// retransmit the holes during loss recovery (RFC 6675)
+#[derive(Default)]
+pub struct Scoreboard {
+    /// disjoint `[start, end)` ranges above SND.UNA, in sequence order
+    ranges: Vec<(u32, u32)>,
+}
+
+fn seq_max(a: u32, b: u32) -> u32 {
+    if seq_lt(a, b) {
+        b
+    } else {
+        a
+    }
+}
+
+fn seq_min(a: u32, b: u32) -> u32 {
+    if seq_lt(a, b) {
+        a
+    } else {
+        b
+    }
+}
+
+impl Scoreboard {
+    /// duplicate ACKs, or segments SACKed above a hole, after which it is
+    /// considered lost
+    const DUP_THRESH: u32 = 3;
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+    }
+
+    fn sacked_bytes(&self) -> u32 {
+        self.ranges
+            .iter()
+            .map(|(start, end)| end.wrapping_sub(*start))
+            .sum()
+    }
+
+    /// records a SACK block, ignoring the parts outside of `[send_unack, send_max)`,
+    /// returns whether it covered data that wasn't SACKed before
+    pub fn insert(&mut self, start: u32, end: u32, send_unack: u32, send_max: u32) -> bool {
+        let start = seq_max(start, send_unack);
+        let end = seq_min(end, send_max);
+        if !seq_lt(start, end) {
+            return false;
+        }
+
+        let sacked = self.sacked_bytes();
+
+        let (mut start, mut end) = (start, end);
+        self.ranges.retain(|(s, e)| {
+            // merge overlapping and adjacent ranges into the new one
+            if seq_le(*s, end) && seq_le(start, *e) {
+                start = seq_min(start, *s);
+                end = seq_max(end, *e);
+                false
+            } else {
+                true
+            }
+        });
+
+        let idx = self
+            .ranges
+            .iter()
+            .position(|(s, _)| seq_lt(start, *s))
+            .unwrap_or(self.ranges.len());
+        self.ranges.insert(idx, (start, end));
+
+        self.sacked_bytes() > sacked
+    }
+
+    /// forgets everything below the new SND.UNA
+    pub fn advance(&mut self, send_unack: u32) {
+        self.ranges.retain_mut(|(start, end)| {
+            if seq_le(*end, send_unack) {
+                return false;
+            }
+
+            *start = seq_max(*start, send_unack);
+            true
+        });
+    }
+
+    /// the SACKed range containing `seq`
+    pub fn range_at(&self, seq: u32) -> Option<(u32, u32)> {
+        self.ranges
+            .iter()
+            .find(|(start, end)| seq_le(*start, seq) && seq_lt(seq, *end))
+            .copied()
+    }
+
+    /// start of the first SACKed range above `seq`
+    pub fn next_sacked(&self, seq: u32) -> Option<u32> {
+        self.ranges
+            .iter()
+            .map(|(start, _)| *start)
+            .find(|start| seq_lt(seq, *start))
+    }
+
+    /// RFC 6675 4, unSACKed data is lost once enough data above it was SACKed
+    pub fn is_lost(&self, seq: u32, mss: u32) -> bool {
+        let mut bytes = 0;
+        let mut ranges = 0;
+
+        for (start, end) in self.ranges.iter().rev() {
+            if seq_lt(*start, seq) {
+                break;
+            }
+
+            bytes += end.wrapping_sub(*start);
+            ranges += 1;
+        }
+
+        ranges >= Self::DUP_THRESH || bytes > (Self::DUP_THRESH - 1) * mss
+    }
+
+    /// unSACKed ranges in `[send_unack, send_max)`, including the one past
+    /// the highest SACKed range
+    fn holes(&self, send_unack: u32, send_max: u32) -> Vec<(u32, u32)> {
+        let mut holes = Vec::new();
+        let mut seq = send_unack;
+
+        for (start, end) in &self.ranges {
+            if seq_lt(seq, *start) {
+                holes.push((seq, *start));
+            }
+            seq = *end;
+        }
+
+        if seq_lt(seq, send_max) {
+            holes.push((seq, send_max));
+        }
+
+        holes
+    }
+
+    /// RFC 6675 4, NextSeg() rule 1, the first lost unSACKed data at or above `from`
+    pub fn next_lost(&self, from: u32, send_unack: u32, mss: u32) -> Option<(u32, u32)> {
+        let highest = self.ranges.last()?.1;
+
+        self.holes(send_unack, highest)
+            .into_iter()
+            .filter(|(_, end)| seq_lt(from, *end))
+            .map(|(start, end)| (seq_max(start, from), end))
+            .find(|(start, _)| self.is_lost(*start, mss))
+    }
+
+    /// RFC 6675 4, SetPipe(), bytes estimated to be in the network; data is
+    /// counted once if it wasn't deemed lost, and again if it was retransmitted
+    /// below `high_rxt`
+    pub fn pipe(&self, send_unack: u32, send_max: u32, high_rxt: u32, mss: u32) -> u32 {
+        self.holes(send_unack, send_max)
+            .into_iter()
+            .map(|(start, end)| {
+                let mut pipe = 0;
+                if !self.is_lost(start, mss) {
+                    pipe += end.wrapping_sub(start);
+                }
+
+                let retransmitted_end = seq_min(end, high_rxt);
+                if seq_lt(start, retransmitted_end) {
+                    pipe += retransmitted_end.wrapping_sub(start);
+                }
+
+                pipe
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: u32 = 100;
+
+    /// a scoreboard for `[base, base + 1000)` outstanding, with the given
+    /// offsets from `base` SACKed
+    fn sacked(base: u32, ranges: &[(u32, u32)]) -> Scoreboard {
+        let mut scoreboard = Scoreboard::default();
+        for (start, end) in ranges {
+            scoreboard.insert(
+                base.wrapping_add(*start),
+                base.wrapping_add(*end),
+                base,
+                base.wrapping_add(1000),
+            );
+        }
+        scoreboard
+    }
+
+    /// the ranges as offsets from `base`
+    fn offsets(scoreboard: &Scoreboard, base: u32) -> Vec<(u32, u32)> {
+        scoreboard
+            .ranges
+            .iter()
+            .map(|(start, end)| (start.wrapping_sub(base), end.wrapping_sub(base)))
+            .collect()
+    }
+
+    #[test]
+    fn insert_merges_and_orders_ranges() {
+        for base in [0, u32::MAX - 450] {
+            let mut scoreboard = Scoreboard::default();
+            let max = base.wrapping_add(1000);
+
+            assert!(scoreboard.insert(base.wrapping_add(500), base.wrapping_add(600), base, max));
+            assert!(scoreboard.insert(base.wrapping_add(100), base.wrapping_add(200), base, max));
+            assert_eq!(offsets(&scoreboard, base), [(100, 200), (500, 600)]);
+
+            // adjacent and overlapping blocks merge
+            assert!(scoreboard.insert(base.wrapping_add(200), base.wrapping_add(300), base, max));
+            assert!(scoreboard.insert(base.wrapping_add(250), base.wrapping_add(550), base, max));
+            assert_eq!(offsets(&scoreboard, base), [(100, 600)]);
+
+            // nothing new
+            assert!(!scoreboard.insert(base.wrapping_add(150), base.wrapping_add(400), base, max));
+            assert_eq!(
+                scoreboard.range_at(base.wrapping_add(450)),
+                Some((base.wrapping_add(100), base.wrapping_add(600)))
+            );
+            assert_eq!(scoreboard.range_at(base.wrapping_add(600)), None);
+        }
+    }
+
+    #[test]
+    fn insert_ignores_blocks_outside_of_the_flight() {
+        for base in [1000_u32, 50] {
+            let mut scoreboard = Scoreboard::default();
+            let max = base.wrapping_add(1000);
+
+            assert!(!scoreboard.insert(base.wrapping_sub(100), base, base, max));
+            assert!(!scoreboard.insert(max, max.wrapping_add(100), base, max));
+            assert!(scoreboard.insert(base.wrapping_sub(50), base.wrapping_add(50), base, max));
+            assert!(scoreboard.insert(max.wrapping_sub(50), max.wrapping_add(50), base, max));
+            assert_eq!(offsets(&scoreboard, base), [(0, 50), (950, 1000)]);
+        }
+    }
+
+    #[test]
+    fn advance_forgets_acknowledged_ranges() {
+        for base in [0, u32::MAX - 250] {
+            let mut scoreboard = sacked(base, &[(100, 200), (300, 400)]);
+
+            scoreboard.advance(base.wrapping_add(150));
+            assert_eq!(offsets(&scoreboard, base), [(150, 200), (300, 400)]);
+
+            scoreboard.advance(base.wrapping_add(350));
+            assert_eq!(offsets(&scoreboard, base), [(350, 400)]);
+
+            scoreboard.advance(base.wrapping_add(400));
+            assert!(scoreboard.is_empty());
+        }
+    }
+
+    #[test]
+    fn next_sacked_finds_the_range_above() {
+        let base = u32::MAX - 150;
+        let scoreboard = sacked(base, &[(100, 200), (300, 400)]);
+
+        assert_eq!(scoreboard.next_sacked(base), Some(base.wrapping_add(100)));
+        assert_eq!(
+            scoreboard.next_sacked(base.wrapping_add(200)),
+            Some(base.wrapping_add(300))
+        );
+        assert_eq!(scoreboard.next_sacked(base.wrapping_add(300)), None);
+    }
+
+    #[test]
+    fn holes_are_lost_below_enough_sacked_data() {
+        for base in [0, u32::MAX - 350] {
+            let scoreboard = sacked(base, &[(100, 200), (300, 400), (500, 600)]);
+
+            // three SACKed ranges above
+            assert!(scoreboard.is_lost(base, MSS));
+            // two ranges holding 2 * MSS, not more
+            assert!(!scoreboard.is_lost(base.wrapping_add(200), MSS));
+
+            assert_eq!(
+                scoreboard.next_lost(base, base, MSS),
+                Some((base, base.wrapping_add(100)))
+            );
+            // the lost hole was already retransmitted
+            assert_eq!(
+                scoreboard.next_lost(base.wrapping_add(100), base, MSS),
+                None
+            );
+
+            // more than (DupThresh - 1) * MSS bytes above is enough too
+            let scoreboard = sacked(base, &[(100, 350)]);
+            assert!(scoreboard.is_lost(base, MSS));
+            assert_eq!(
+                scoreboard.next_lost(base.wrapping_add(50), base, MSS),
+                Some((base.wrapping_add(50), base.wrapping_add(100)))
+            );
+        }
+    }
+
+    #[test]
+    fn pipe_counts_unsacked_and_retransmitted_data() {
+        for base in [0, u32::MAX - 550] {
+            let scoreboard = sacked(base, &[(100, 200), (300, 400), (500, 600)]);
+            let max = base.wrapping_add(1000);
+
+            // the holes at 200, 400 and 600 aren't lost, the one at 0 is
+            assert_eq!(scoreboard.pipe(base, max, base, MSS), 600);
+            // retransmitting the lost hole puts it back in flight
+            assert_eq!(scoreboard.pipe(base, max, base.wrapping_add(100), MSS), 700);
+        }
+    }
+}
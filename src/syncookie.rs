@@ -0,0 +1,224 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+use crate::tcp::{Quad, MAX_WINDOW_SCALE};
+
+/// MSS values a cookie can encode, the peer's is rounded down to one of them
+const MSS_TABLE: [u16; 4] = [536, 1300, 1440, 1460];
+
+/// minutes a cookie stays valid for
+const MAX_AGE: u32 = 2;
+
+/// how long cookies are accepted after the last one was sent
+pub const LIFETIME: Duration = Duration::from_secs(MAX_AGE as u64 * 60);
+
+/// low bits of the TSval carrying the options of a cookie
+const TS_OPTION_BITS: u32 = 6;
+
+/// window scale field value for a peer that didn't offer the option
+const NO_WINDOW_SCALE: u32 = 0xF;
+
+/// stateless SYN-ACKs for listeners under SYN floods (RFC 4987 3.6), the
+/// connection is rebuilt from the ACK of a sequence number only this listener
+/// could have chosen; the layout follows Linux:
+///
+/// ISS = H(quad) + peer ISS + (minute << 24) + ((H(quad, minute) + MSS index) & 0xFFFFFF)
+pub struct SynCookies {
+    /// SipHash keyed with random secrets
+    key: RandomState,
+    clock: Instant,
+}
+
+impl Default for SynCookies {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SynCookies {
+    pub fn new() -> Self {
+        Self {
+            key: RandomState::new(),
+            clock: Instant::now(),
+        }
+    }
+
+    fn minute(&self) -> u32 {
+        (self.clock.elapsed().as_secs() / 60) as u32
+    }
+
+    fn hash(&self, quad: &Quad, minute: Option<u32>) -> u32 {
+        let mut hasher = self.key.build_hasher();
+        quad.hash(&mut hasher);
+        minute.hash(&mut hasher);
+        hasher.finish() as u32
+    }
+
+    /// our ISS for a SYN with sequence number `seq`, along with the MSS it encodes
+    pub fn generate(&self, quad: &Quad, seq: u32, peer_mss: u16) -> (u32, u16) {
+        self.generate_at(quad, seq, peer_mss, self.minute())
+    }
+
+    fn generate_at(&self, quad: &Quad, seq: u32, peer_mss: u16, minute: u32) -> (u32, u16) {
+        let index = MSS_TABLE
+            .iter()
+            .rposition(|mss| *mss <= peer_mss)
+            .unwrap_or(0);
+
+        let cookie = self
+            .hash(quad, None)
+            .wrapping_add(seq)
+            .wrapping_add(minute << 24)
+            .wrapping_add(self.hash(quad, Some(minute)).wrapping_add(index as u32) & 0xFFFFFF);
+
+        (cookie, MSS_TABLE[index])
+    }
+
+    /// checks the cookie acknowledged by the ACK completing the handshake,
+    /// returning the MSS it encodes
+    pub fn check(&self, quad: &Quad, seq: u32, ack: u32) -> Option<u16> {
+        self.check_at(quad, seq, ack, self.minute())
+    }
+
+    fn check_at(&self, quad: &Quad, seq: u32, ack: u32, minute: u32) -> Option<u16> {
+        let cookie = ack
+            .wrapping_sub(1)
+            .wrapping_sub(self.hash(quad, None))
+            .wrapping_sub(seq.wrapping_sub(1));
+
+        let age = minute.wrapping_sub(cookie >> 24) & 0xFF;
+        if age >= MAX_AGE {
+            return None;
+        }
+
+        let index = cookie.wrapping_sub(self.hash(quad, Some(minute.wrapping_sub(age)))) & 0xFFFFFF;
+        MSS_TABLE.get(index as usize).copied()
+    }
+}
+
+/// hides the peer's window scale and SACK-Permitted in the low bits of our
+/// TSval, the only other part of the SYN-ACK the peer echoes back
+pub fn encode_options(ts_now: u32, window_scale: Option<u8>, sack_permitted: bool) -> u32 {
+    let options = window_scale.map_or(NO_WINDOW_SCALE, u32::from) | (sack_permitted as u32) << 4;
+    let ts_val = (ts_now & !((1 << TS_OPTION_BITS) - 1)) | options;
+
+    // the TSval mustn't run ahead of the clock the connection continues with
+    if ts_val > ts_now {
+        ts_val.wrapping_sub(1 << TS_OPTION_BITS)
+    } else {
+        ts_val
+    }
+}
+
+/// the window scale and SACK-Permitted hidden in the TSecr of the final ACK,
+/// `None` if the encoding is invalid
+pub fn decode_options(ts_ecr: u32) -> Option<(Option<u8>, bool)> {
+    let window_scale = ts_ecr & NO_WINDOW_SCALE;
+    let sack_permitted = ts_ecr & (1 << 4) != 0;
+
+    match window_scale {
+        NO_WINDOW_SCALE => Some((None, sack_permitted)),
+        shift if shift <= MAX_WINDOW_SCALE as u32 => Some((Some(shift as u8), sack_permitted)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad() -> Quad {
+        (
+            "10.0.0.1:9000".parse().unwrap(),
+            "10.0.0.2:40000".parse().unwrap(),
+        )
+    }
+
+    /// checks the final ACK of a handshake answered with `cookie`
+    fn check(cookies: &SynCookies, seq: u32, cookie: u32, minute: u32) -> Option<u16> {
+        cookies.check_at(&quad(), seq.wrapping_add(1), cookie.wrapping_add(1), minute)
+    }
+
+    #[test]
+    fn cookie_round_trips() {
+        let cookies = SynCookies::new();
+
+        for seq in [0, 1, 0x7FFF_FFFF, u32::MAX] {
+            for minute in [0, 1, 255, 256, 1000] {
+                let (cookie, mss) = cookies.generate_at(&quad(), seq, 1460, minute);
+                assert_eq!(mss, 1460);
+                assert_eq!(check(&cookies, seq, cookie, minute), Some(1460));
+            }
+        }
+    }
+
+    #[test]
+    fn mss_is_rounded_down_to_the_table() {
+        let cookies = SynCookies::new();
+
+        for (peer_mss, encoded) in [
+            (9000, 1460),
+            (1460, 1460),
+            (1400, 1300),
+            (1000, 536),
+            (88, 536),
+        ] {
+            let (cookie, mss) = cookies.generate_at(&quad(), 1000, peer_mss, 0);
+            assert_eq!(mss, encoded);
+            assert_eq!(check(&cookies, 1000, cookie, 0), Some(encoded));
+        }
+    }
+
+    #[test]
+    fn cookie_expires() {
+        let cookies = SynCookies::new();
+        let (cookie, _) = cookies.generate_at(&quad(), 1000, 1460, 10);
+
+        assert_eq!(check(&cookies, 1000, cookie, 11), Some(1460));
+        assert_eq!(check(&cookies, 1000, cookie, 10 + MAX_AGE), None);
+        // nor is it valid before it was sent
+        assert_eq!(check(&cookies, 1000, cookie, 9), None);
+    }
+
+    #[test]
+    fn cookie_is_bound_to_the_connection() {
+        let cookies = SynCookies::new();
+        let (cookie, _) = cookies.generate_at(&quad(), 1000, 1460, 0);
+
+        // the low bits only select the MSS, anything past the table is invalid
+        assert_eq!(check(&cookies, 1000, cookie.wrapping_add(1), 0), None);
+        assert_eq!(check(&cookies, 1000, cookie.wrapping_sub(1000), 0), None);
+        assert_eq!(check(&cookies, 2000, cookie, 0), None);
+
+        let other = (quad().0, "10.0.0.2:40001".parse().unwrap());
+        assert_eq!(
+            cookies.check_at(&other, 1001, cookie.wrapping_add(1), 0),
+            None
+        );
+
+        // and to the secret of the listener
+        assert_eq!(check(&SynCookies::new(), 1000, cookie, 0), None);
+    }
+
+    #[test]
+    fn options_round_trip() {
+        for window_scale in [None, Some(0), Some(7), Some(MAX_WINDOW_SCALE)] {
+            for sack_permitted in [false, true] {
+                let ts_val = encode_options(123_456_789, window_scale, sack_permitted);
+                assert_eq!(decode_options(ts_val), Some((window_scale, sack_permitted)));
+            }
+        }
+    }
+
+    #[test]
+    fn encoded_tsval_does_not_run_ahead_of_the_clock() {
+        for ts_now in [0, 1, 63, 64, 1000, u32::MAX] {
+            let ts_val = encode_options(ts_now, Some(14), true);
+            assert!(crate::tcp::seq_le(ts_val, ts_now));
+            assert!(ts_now.wrapping_sub(ts_val) < 1 << TS_OPTION_BITS);
+        }
+    }
+}
@@ -1,11 +1,284 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap, VecDeque},
     io::{Read, Write},
     net::SocketAddrV4,
-    sync::{mpsc, Arc, Condvar, Mutex},
+    sync::{mpsc, Arc, Condvar, Mutex, Weak},
 };
 use tracing::{debug, error, info, warn};
 
+use crate::{
+    congestion::{CongestionAlgorithm, CongestionControl, RateSample},
+    isn::IsnGenerator,
+    sack::Scoreboard,
+    syncookie::{self, SynCookies},
+};
+
+/// (local address, remote address)
+pub type Quad = (SocketAddrV4, SocketAddrV4);
+
+/// MSS to assume if the peer doesn't send the option (RFC 9293 3.7.1)
+const DEFAULT_MSS: u16 = 536;
+
+/// smallest peer MSS we accept, smaller values leave no room for payload once
+/// options are added (TCP_MIN_SND_MSS in Linux)
+const MIN_SEND_MSS: u16 = 88;
+
+/// maximum amount of in-order data buffered for the user
+const RECV_BUFFER_SIZE: usize = 1 << 20;
+
+/// size of the ring buffer holding unacknowledged data, a power of two so that
+/// sequence numbers map onto it across wraparound
+const SEND_BUFFER_SIZE: usize = 1 << 20;
+
+/// largest shift allowed for the window scale option (RFC 7323 2.3)
+pub(crate) const MAX_WINDOW_SCALE: u8 = 14;
+
+/// shift we offer, so the whole receive buffer can be advertised in the 16 bit
+/// window field
+const RECV_WINDOW_SCALE: u8 = {
+    let mut shift = 0;
+    while RECV_BUFFER_SIZE >> shift > 0xFFFF {
+        shift += 1;
+    }
+    shift
+};
+
+/// how long an ACK can be delayed by default, RFC 9293 3.8.6.3 requires less
+/// than 0.5 seconds
+const DEFAULT_ACK_DELAY: std::time::Duration = std::time::Duration::from_millis(40);
+
+/// how long a partial segment can be held back by SWS avoidance (RFC 1122 4.2.3.4)
+const SWS_OVERRIDE_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// unanswered zero window probes after which the connection is aborted
+const MAX_PERSIST_PROBES: u32 = 15;
+
+/// SACK blocks that fit in the option space next to the NOPs aligning them (RFC 2018 3)
+const MAX_SACK_BLOCKS: usize = 4;
+
+/// SACK blocks that fit next to the timestamp option
+const MAX_SACK_BLOCKS_WITH_TIMESTAMPS: usize = 3;
+
+/// idle time after which TS.Recent is too old to compare against (RFC 7323 5.5)
+const PAWS_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(24 * 24 * 60 * 60);
+
+/// kind of the user timeout option (RFC 5482 3)
+const USER_TIMEOUT_OPTION: u8 = 28;
+
+/// challenge ACKs a device sends per second on average (RFC 5961 7)
+pub const DEFAULT_CHALLENGE_ACK_LIMIT: u32 = 1000;
+
+/// half-open connections a listener keeps before answering SYNs with cookies
+const MAX_SYN_BACKLOG: usize = 256;
+
+/// duplicate ACKs that trigger a fast retransmit (RFC 5681 3.2)
+const DUP_ACK_THRESHOLD: u32 = 3;
+
+/// how long paced transmissions can fall behind before the unused budget is
+/// dropped, roughly how often `tick()` runs
+const PACING_QUANTUM: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// sequence number comparison modulo 2^32 (RFC 9293 3.4)
+pub(crate) fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+pub(crate) fn seq_le(a: u32, b: u32) -> bool {
+    !seq_lt(b, a)
+}
+
+/// splits raw options into `(kind, option)` pairs, stopping at the end of
+/// option list or at a malformed length
+fn raw_options(options: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut rest = options;
+    std::iter::from_fn(move || loop {
+        match *rest {
+            [] | [0, ..] => return None,
+            [1, ..] => rest = &rest[1..],
+            [kind, len, ..] if len >= 2 && len as usize <= rest.len() => {
+                let (option, tail) = rest.split_at(len as usize);
+                rest = tail;
+                return Some((kind, option));
+            }
+            _ => {
+                warn!("received malformed TCP option");
+                return None;
+            }
+        }
+    })
+}
+
+/// the segment's options, etherparse's own iterator gives up at the first kind
+/// it doesn't know, which would hide everything behind the user timeout option
+fn tcp_options<'a>(
+    pkt: &'a etherparse::TcpSlice,
+) -> impl Iterator<Item = Result<etherparse::TcpOptionElement, etherparse::TcpOptionReadError>> + 'a
+{
+    raw_options(pkt.options())
+        .filter_map(|(_, option)| etherparse::TcpOptionsIterator::from_slice(option).next())
+        .filter(|option| !matches!(option, Err(etherparse::TcpOptionReadError::UnknownId(_))))
+}
+
+/// the user timeout the peer advertised (RFC 5482 3)
+fn user_timeout_option(pkt: &etherparse::TcpSlice) -> Option<std::time::Duration> {
+    raw_options(pkt.options()).find_map(|(kind, option)| match *option {
+        [USER_TIMEOUT_OPTION, 4, high, low] => {
+            let timeout = u16::from_be_bytes([high & 0x7F, low]) as u64;
+            // the granularity bit selects minutes over seconds
+            Some(std::time::Duration::from_secs(if high & 0x80 != 0 {
+                timeout * 60
+            } else {
+                timeout
+            }))
+        }
+        _ if kind == USER_TIMEOUT_OPTION => {
+            warn!("received user timeout option with invalid length");
+            None
+        }
+        _ => None,
+    })
+}
+
+/// prepends the user timeout option to `options`, as long as it fits, since
+/// etherparse can't build it
+fn with_user_timeout_option(
+    options: &etherparse::TcpOptions,
+    timeout: std::time::Duration,
+) -> Option<etherparse::TcpOptions> {
+    // the timeout is sent in minutes if it doesn't fit in 15 bits of seconds
+    let secs = timeout.as_secs();
+    let field = if secs <= 0x7FFF {
+        secs as u16
+    } else {
+        0x8000 | secs.div_ceil(60).min(0x7FFF) as u16
+    };
+
+    let mut raw = vec![USER_TIMEOUT_OPTION, 4];
+    raw.extend(field.to_be_bytes());
+    raw.extend(options.as_slice());
+    etherparse::TcpOptions::try_from_slice(&raw).ok()
+}
+
+/// MSS we advertise on a device with the given MTU
+fn local_mss(mtu: u16) -> u16 {
+    mtu - (etherparse::Ipv4Header::MIN_LEN + etherparse::TcpHeader::MIN_LEN) as u16
+}
+
+/// sends a segment without payload for a connection there is no TCB for
+fn transmit_header(quad: Quad, header: etherparse::TcpHeader, tx: &mpsc::Sender<Vec<u8>>) {
+    let tcp = etherparse::PacketBuilder::ipv4(quad.0.ip().octets(), quad.1.ip().octets(), 64)
+        .tcp_header(header);
+    let mut result = Vec::with_capacity(tcp.size(0));
+    tcp.write(&mut result, &[]).unwrap();
+    tx.send(result).unwrap();
+}
+
+/// RFC 9293 3.10.7.2, resets the connection an unexpected ACK belongs to,
+/// <SEQ=SEG.ACK><CTL=RST>
+fn transmit_rst(quad: Quad, pkt: &etherparse::TcpSlice, tx: &mpsc::Sender<Vec<u8>>) {
+    let mut header =
+        etherparse::TcpHeader::new(quad.0.port(), quad.1.port(), pkt.acknowledgment_number(), 0);
+    header.rst = true;
+    transmit_header(quad, header, tx);
+}
+
+/// TSval and TSecr of the segment's timestamp option
+fn timestamp_option(pkt: &etherparse::TcpSlice) -> Option<(u32, u32)> {
+    tcp_options(pkt).find_map(|option| match option {
+        Ok(etherparse::TcpOptionElement::Timestamp(ts_val, ts_ecr)) => Some((ts_val, ts_ecr)),
+        _ => None,
+    })
+}
+
+/// options that are only sent on SYN segments, either the peer's or our own
+struct SynOptions {
+    mss: u16,
+    sack_permitted: bool,
+    window_scale: Option<u8>,
+    /// TSval and TSecr
+    timestamp: Option<(u32, u32)>,
+}
+
+impl SynOptions {
+    /// the options of the peer's SYN, the MSS and window scale are clamped to
+    /// what we can work with
+    fn parse(pkt: &etherparse::TcpSlice) -> Self {
+        let mut options = Self {
+            mss: DEFAULT_MSS,
+            sack_permitted: false,
+            window_scale: None,
+            timestamp: None,
+        };
+
+        for option in tcp_options(pkt) {
+            match option {
+                Ok(etherparse::TcpOptionElement::MaximumSegmentSize(mss)) => options.mss = mss,
+                Ok(etherparse::TcpOptionElement::SelectiveAcknowledgementPermitted) => {
+                    options.sack_permitted = true
+                }
+                Ok(etherparse::TcpOptionElement::WindowScale(shift)) => {
+                    options.window_scale = Some(shift)
+                }
+                Ok(etherparse::TcpOptionElement::Timestamp(ts_val, ts_ecr)) => {
+                    options.timestamp = Some((ts_val, ts_ecr))
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("failed to parse TCP option: {e}");
+                    break;
+                }
+            }
+        }
+
+        if options.mss < MIN_SEND_MSS {
+            warn!(
+                peer_mss = options.mss,
+                "peer offered an MSS below the minimum"
+            );
+            options.mss = MIN_SEND_MSS;
+        }
+
+        if let Some(shift) = options
+            .window_scale
+            .filter(|shift| *shift > MAX_WINDOW_SCALE)
+        {
+            warn!(shift, "peer offered a window scale above the maximum");
+            options.window_scale = Some(MAX_WINDOW_SCALE);
+        }
+
+        options
+    }
+
+    fn elements(&self) -> Vec<etherparse::TcpOptionElement> {
+        let mut options = vec![etherparse::TcpOptionElement::MaximumSegmentSize(self.mss)];
+
+        if self.sack_permitted {
+            options.extend([
+                etherparse::TcpOptionElement::Noop,
+                etherparse::TcpOptionElement::Noop,
+                etherparse::TcpOptionElement::SelectiveAcknowledgementPermitted,
+            ]);
+        }
+
+        if let Some(shift) = self.window_scale {
+            options.extend([
+                etherparse::TcpOptionElement::Noop,
+                etherparse::TcpOptionElement::WindowScale(shift),
+            ]);
+        }
+
+        if let Some((ts_val, ts_ecr)) = self.timestamp {
+            options.extend([
+                etherparse::TcpOptionElement::Noop,
+                etherparse::TcpOptionElement::Noop,
+                etherparse::TcpOptionElement::Timestamp(ts_val, ts_ecr),
+            ]);
+        }
+
+        options
+    }
+}
+
 #[derive(Clone, Debug)]
 enum TcpState {
     Listen,
@@ -27,26 +300,258 @@ impl std::fmt::Display for TcpState {
     }
 }
 
+/// keepalive probing of idle connections (RFC 1122 4.2.3.6)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Keepalive {
+    /// time without segments from the peer before the first probe
+    pub idle: std::time::Duration,
+    /// time between unanswered probes
+    pub interval: std::time::Duration,
+    /// unanswered probes after which the connection times out
+    pub count: u32,
+}
+
+impl Default for Keepalive {
+    /// the idle time must default to no less than two hours (RFC 1122 4.2.3.6)
+    fn default() -> Self {
+        Self {
+            idle: std::time::Duration::from_secs(2 * 60 * 60),
+            interval: std::time::Duration::from_secs(75),
+            count: 9,
+        }
+    }
+}
+
+/// when to stop retransmitting the same segment (RFC 1122 4.2.3.5), the
+/// connection is aborted once either R2 limit is reached
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetransmitLimits {
+    /// R1, retransmissions after which the path is reported as failing
+    pub r1: u32,
+    /// R2, retransmissions after which the connection is aborted
+    pub r2: u32,
+    /// R2 in time, since the first retransmission of the segment
+    pub timeout: std::time::Duration,
+}
+
+impl RetransmitLimits {
+    /// R2 for a SYN must cover at least 3 minutes (RFC 1122 4.2.3.5)
+    pub const SYN: Self = Self {
+        r1: 3,
+        r2: 8,
+        timeout: std::time::Duration::from_secs(3 * 60),
+    };
+
+    /// R2 for data must cover at least 100 seconds (RFC 1122 4.2.3.5), with
+    /// the RTO capped at 60 seconds 15 retransmissions take about 10 minutes
+    pub const DATA: Self = Self {
+        r1: 3,
+        r2: 15,
+        timeout: std::time::Duration::from_secs(15 * 60),
+    };
+}
+
+/// bounds the challenge ACKs sent by all connections of a device, so blind
+/// attackers can't use them to flood the peer (RFC 5961 7)
+pub struct ChallengeAckLimiter {
+    limit: u32,
+    /// start of the current one second interval and the ACKs left in it
+    budget: Mutex<(std::time::Instant, u32)>,
+}
+
+impl ChallengeAckLimiter {
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            // the first interval starts with a full budget
+            budget: Mutex::new((std::time::Instant::now(), Self::budget(limit))),
+        }
+    }
+
+    /// the budget of every interval is randomized so that it can't be probed
+    /// as a side channel (CVE-2016-5696)
+    fn budget(limit: u32) -> u32 {
+        limit / 2 + rand::random_range(0..=limit)
+    }
+
+    /// returns whether a challenge ACK can be sent now
+    fn allow(&self) -> bool {
+        let mut budget = self.budget.lock().unwrap();
+        if budget.0.elapsed() >= std::time::Duration::from_secs(1) {
+            *budget = (std::time::Instant::now(), Self::budget(self.limit));
+        }
+
+        if budget.1 == 0 {
+            return false;
+        }
+
+        budget.1 -= 1;
+        true
+    }
+}
+
+/// a transmitted segment waiting to be acknowledged
+struct SentSegment {
+    retransmitted: bool,
+    /// when the segment was last transmitted, drives the retransmission timer
+    sent: std::time::Instant,
+    /// delivery rate estimation state when the segment was sent
+    /// (draft-cheng-iccrg-delivery-rate-estimation)
+    delivered: u64,
+    delivered_time: std::time::Instant,
+    first_sent_time: std::time::Instant,
+    app_limited: bool,
+}
+
 pub struct TcpSocket {
     source_ip: [u8; 4],
     destination_ip: [u8; 4],
     header: etherparse::TcpHeader,
     send_unack: u32,
     send_next: u32,
+    /// end of the data written by the user, `[SND.NXT, send_queued)` hasn't
+    /// been transmitted yet
+    send_queued: u32,
+    /// highest sequence number sent so far, SND.NXT goes back to SND.UNA when
+    /// the retransmission timer expires while this stays put
+    send_max: u32,
+    /// SND.WND
+    send_wnd: u32,
+    /// SND.WL1, sequence number of the segment used for the last window update
+    send_wl1: u32,
+    /// SND.WL2, acknowledgment number of the segment used for the last window update
+    send_wl2: u32,
+    /// largest window the peer has offered
+    max_send_wnd: u32,
     recv_next: u32,
-    send_window: Vec<u8>,
-    recv_window: Vec<u8>,
+    /// right edge of the window we advertised, RCV.NXT + RCV.WND
+    recv_edge: u32,
+    send_buffer: Vec<u8>,
+    /// in-order data waiting to be read, up to `RECV_BUFFER_SIZE`
+    recv_window: VecDeque<u8>,
     srtt: f64,
     rttvar: f64,
     rto: f64,
     syn_seq: u32,
     fin_seq: Option<u32>,
+    /// MSS we advertise, derived from the device MTU
+    local_mss: u16,
+    /// largest segment we can send, min(peer MSS, local MSS)
+    send_mss: u16,
+    /// both sides sent SACK-Permitted in the handshake (RFC 2018)
+    sack_permitted: bool,
+    /// blocks of out-of-order data to report, the one holding the most
+    /// recently received segment first
+    sack_blocks: Vec<(u32, u32)>,
+    /// duplicate data to report in front of the SACK blocks of the next
+    /// segment, D-SACK (RFC 2883)
+    dsack_block: Option<(u32, u32)>,
+    /// both sides sent the window scale option in the handshake (RFC 7323 2)
+    window_scaling: bool,
+    /// shift applied to the windows the peer advertises
+    send_wscale: u8,
+    /// shift applied to the windows we advertise
+    recv_wscale: u8,
+    /// both sides sent the timestamp option in the handshake (RFC 7323)
+    ts_enabled: bool,
+    /// TS.Recent, the timestamp to echo in TSecr
+    ts_recent: u32,
+    /// when TS.Recent was last updated
+    ts_recent_instant: std::time::Instant,
+    /// random offset of our timestamp clock (RFC 7323 5.4)
+    ts_offset: u32,
+    ts_clock: std::time::Instant,
+    /// Last.ACK.sent, the acknowledgment number of the last segment we sent
+    last_ack_sent: u32,
+    /// data the peer SACKed
+    scoreboard: Scoreboard,
+    /// HighRxt, end of the data retransmitted during SACK recovery (RFC 6675)
+    high_rxt: u32,
+    /// disables Nagle's algorithm (TCP_NODELAY)
+    nodelay: bool,
+    /// how long the ACK for a single segment can be delayed, zero
+    /// acknowledges every segment right away
+    ack_delay: std::time::Duration,
+    /// when the first segment the pending ACK was delayed for arrived
+    delayed_ack: Option<std::time::Instant>,
+    /// segments received since the last ACK was sent
+    delayed_segments: u32,
+    /// when a partial segment was first held back by SWS avoidance
+    sws_timer: Option<std::time::Instant>,
+    /// when the last zero window probe was sent, or when the peer's window closed
+    persist_instant: Option<std::time::Instant>,
+    /// exponential backoff of the persist timer, reset once the window opens
+    persist_backoff: u32,
+    /// zero window probes sent since the last ACK
+    persist_probes: u32,
+    congestion_algorithm: CongestionAlgorithm,
+    congestion: Box<dyn CongestionControl>,
+    /// duplicate ACKs received in a row
+    dup_acks: u32,
+    /// highest sequence number sent when fast recovery was last entered (RFC 6582)
+    recover: u32,
+    /// congestion window during fast recovery, inflated by every duplicate ACK
+    /// and deflated by partial ACKs (RFC 6582)
+    recovery_cwnd: Option<u32>,
+    /// SND.UNA when the congestion window was last reduced, as long as the
+    /// reduction can still be undone
+    undo_marker: Option<u32>,
+    /// bytes retransmitted since the last reduction that the peer didn't
+    /// report as duplicates yet
+    undo_retrans: u32,
+    /// segments retransmitted so far
+    retransmits: u64,
+    /// retransmissions the peer reported as duplicates with D-SACK (RFC 3708)
+    spurious_retransmits: u64,
+    /// bytes acknowledged by the peer so far
+    delivered: u64,
+    /// when `delivered` last changed
+    delivered_time: std::time::Instant,
+    /// send time of the most recently acknowledged segment, the start of the
+    /// sending interval for new rate samples
+    first_sent_time: std::time::Instant,
+    /// value of `delivered` at which the sender stops being application
+    /// limited, 0 if it isn't
+    app_limited_until: u64,
+    /// bytes that can be sent right away without exceeding the pacing rate
+    pacing_budget: f64,
+    pacing_instant: std::time::Instant,
     state: TcpState,
     state_condvar: Arc<Condvar>,
     tx: mpsc::Sender<Vec<u8>>,
+    challenge_acks: Arc<ChallengeAckLimiter>,
     partial_segments: BTreeMap<u32, Vec<u8>>,
-    timers: BTreeMap<u32, (bool, std::time::Instant)>,
+    timers: BTreeMap<u32, SentSegment>,
     time_wait_instant: Option<std::time::Instant>,
+    keepalive: Option<Keepalive>,
+    /// keepalive probes sent since the last segment from the peer
+    keepalive_probes: u32,
+    /// when the last acceptable segment from the peer arrived
+    last_recv_instant: std::time::Instant,
+    /// why the connection failed, returned to readers and writers
+    error: Option<std::io::ErrorKind>,
+    syn_limits: RetransmitLimits,
+    data_limits: RetransmitLimits,
+    /// retransmission timeouts since SND.UNA last advanced
+    timeouts: u32,
+    /// when the first of those timeouts expired
+    first_timeout_instant: Option<std::time::Instant>,
+    /// how long sent data can go unacknowledged before the connection is
+    /// aborted (TCP_USER_TIMEOUT)
+    user_timeout: Option<std::time::Duration>,
+    /// send the user timeout to the peer in the UTO option (RFC 5482)
+    advertise_user_timeout: bool,
+    /// the user timeout changed since it was last advertised
+    user_timeout_pending: bool,
+    /// the user timeout the peer advertised
+    peer_user_timeout: Option<std::time::Duration>,
+    /// when SND.UNA last advanced, or when data was sent with nothing outstanding
+    unacked_since: Option<std::time::Instant>,
+    /// the user shut down reading, received data is dropped
+    read_shutdown: bool,
+    /// the user shut down writing during the handshake, the FIN is queued
+    /// once it completes
+    fin_deferred: bool,
 }
 
 pub struct TcpSocketWrapper {
@@ -62,12 +567,55 @@ impl TcpSocketWrapper {
         }
     }
 
-    pub fn connect(&self) {
+    /// blocks until the handshake completes, failing with `ConnectionRefused`
+    /// if the peer resets it or `TimedOut` if the SYN retransmissions give up
+    pub fn connect(&self) -> std::io::Result<()> {
+        self.connect_until(None)
+    }
+
+    /// like `connect`, but gives up with `TimedOut` after `timeout`
+    pub fn connect_timeout(&self, timeout: std::time::Duration) -> std::io::Result<()> {
+        if timeout.is_zero() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "cannot set a 0 duration timeout",
+            ));
+        }
+
+        self.connect_until(Some(std::time::Instant::now() + timeout))
+    }
+
+    fn connect_until(&self, deadline: Option<std::time::Instant>) -> std::io::Result<()> {
         let mut socket = self.socket.lock().unwrap();
         socket.connect();
 
-        while !matches!(socket.state, TcpState::Established) {
-            socket = self.state_condvar.wait(socket).unwrap();
+        // a simultaneous open goes through SYN-RECEIVED as well
+        while let TcpState::SynSent | TcpState::SynReceived = socket.state {
+            match deadline {
+                Some(deadline) => {
+                    let now = std::time::Instant::now();
+                    if now >= deadline {
+                        warn!("connection attempt timed out");
+                        socket.abort(std::io::ErrorKind::TimedOut);
+                        break;
+                    }
+
+                    socket = self
+                        .state_condvar
+                        .wait_timeout(socket, deadline - now)
+                        .unwrap()
+                        .0;
+                }
+                None => socket = self.state_condvar.wait(socket).unwrap(),
+            }
+        }
+
+        match socket.state {
+            TcpState::Closed => Err(socket
+                .error
+                .unwrap_or(std::io::ErrorKind::ConnectionRefused)
+                .into()),
+            _ => Ok(()),
         }
     }
 
@@ -78,6 +626,100 @@ impl TcpSocketWrapper {
     pub fn close(&self) {
         self.socket.lock().unwrap().close();
     }
+
+    /// shuts down reading, writing or both, like `std::net::TcpStream::shutdown`
+    pub fn shutdown(&self, how: std::net::Shutdown) -> std::io::Result<()> {
+        self.socket.lock().unwrap().shutdown(how)
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) {
+        self.socket.lock().unwrap().set_nodelay(nodelay);
+    }
+
+    pub fn nodelay(&self) -> bool {
+        self.socket.lock().unwrap().nodelay
+    }
+
+    pub fn set_ack_delay(&self, delay: std::time::Duration) {
+        self.socket.lock().unwrap().set_ack_delay(delay);
+    }
+
+    pub fn ack_delay(&self) -> std::time::Duration {
+        self.socket.lock().unwrap().ack_delay
+    }
+
+    /// enables keepalive probing with the given parameters, or disables it
+    pub fn set_keepalive(&self, keepalive: Option<Keepalive>) {
+        self.socket.lock().unwrap().set_keepalive(keepalive);
+    }
+
+    pub fn keepalive(&self) -> Option<Keepalive> {
+        self.socket.lock().unwrap().keepalive
+    }
+
+    /// limits for retransmitting the SYN or SYN-ACK
+    pub fn set_syn_retransmit_limits(&self, limits: RetransmitLimits) {
+        self.socket.lock().unwrap().syn_limits = limits;
+    }
+
+    pub fn syn_retransmit_limits(&self) -> RetransmitLimits {
+        self.socket.lock().unwrap().syn_limits
+    }
+
+    /// limits for retransmitting data and FIN segments
+    pub fn set_retransmit_limits(&self, limits: RetransmitLimits) {
+        self.socket.lock().unwrap().data_limits = limits;
+    }
+
+    pub fn retransmit_limits(&self) -> RetransmitLimits {
+        self.socket.lock().unwrap().data_limits
+    }
+
+    /// aborts the connection with `TimedOut` once sent data goes unacknowledged
+    /// for `timeout`, on top of the retransmission limits
+    pub fn set_user_timeout(&self, timeout: Option<std::time::Duration>) {
+        self.socket.lock().unwrap().set_user_timeout(timeout);
+    }
+
+    pub fn user_timeout(&self) -> Option<std::time::Duration> {
+        self.socket.lock().unwrap().user_timeout
+    }
+
+    /// announces the user timeout to the peer with the UTO option (RFC 5482)
+    pub fn set_advertise_user_timeout(&self, advertise: bool) {
+        self.socket
+            .lock()
+            .unwrap()
+            .set_advertise_user_timeout(advertise);
+    }
+
+    pub fn advertise_user_timeout(&self) -> bool {
+        self.socket.lock().unwrap().advertise_user_timeout
+    }
+
+    /// the user timeout the peer advertised, left for the application to adopt
+    pub fn peer_user_timeout(&self) -> Option<std::time::Duration> {
+        self.socket.lock().unwrap().peer_user_timeout
+    }
+
+    pub fn set_congestion_control(&self, algorithm: CongestionAlgorithm) {
+        self.socket
+            .lock()
+            .unwrap()
+            .set_congestion_control(algorithm);
+    }
+
+    pub fn congestion_control(&self) -> CongestionAlgorithm {
+        self.socket.lock().unwrap().congestion_algorithm
+    }
+
+    pub fn retransmits(&self) -> u64 {
+        self.socket.lock().unwrap().retransmits
+    }
+
+    pub fn spurious_retransmits(&self) -> u64 {
+        self.socket.lock().unwrap().spurious_retransmits
+    }
 }
 
 impl Write for &TcpSocketWrapper {
@@ -115,31 +757,341 @@ impl Read for &TcpSocketWrapper {
     }
 }
 
+/// Connections for a listening port, shared between the listener and the reader thread
+pub struct ListenQueue {
+    /// half-open connections that haven't completed the handshake yet, owned
+    /// by the socket table
+    syn_queue: HashMap<Quad, Weak<Mutex<TcpSocket>>>,
+    /// connections that completed the handshake, waiting for `accept()`
+    accept_queue: VecDeque<(SocketAddrV4, Arc<Mutex<TcpSocket>>)>,
+    state_condvar: Arc<Condvar>,
+    cookies: SynCookies,
+    /// when the SYN queue last overflowed, cookies are only accepted for a
+    /// while after that
+    cookie_instant: Option<std::time::Instant>,
+    /// random offset of the timestamp clock of SYN cookies, connections
+    /// rebuilt from a cookie carry on from it
+    ts_offset: u32,
+    ts_clock: std::time::Instant,
+}
+
+pub struct TcpListener {
+    local_addr: SocketAddrV4,
+    queue: Arc<Mutex<ListenQueue>>,
+    state_condvar: Arc<Condvar>,
+}
+
+impl TcpListener {
+    pub fn new(
+        local_addr: SocketAddrV4,
+        queue: Arc<Mutex<ListenQueue>>,
+        state_condvar: Arc<Condvar>,
+    ) -> Self {
+        Self {
+            local_addr,
+            queue,
+            state_condvar,
+        }
+    }
+
+    pub fn local_addr(&self) -> SocketAddrV4 {
+        self.local_addr
+    }
+
+    /// blocks until a connection completes the handshake, returning it along with the remote address
+    pub fn accept(&self) -> std::io::Result<(TcpSocketWrapper, SocketAddrV4)> {
+        let mut queue = self.queue.lock().unwrap();
+
+        loop {
+            if let Some((remote_addr, socket)) = queue.accept_queue.pop_front() {
+                let condvar = socket.lock().unwrap().state_condvar();
+                return Ok((TcpSocketWrapper::new(socket, condvar), remote_addr));
+            }
+
+            queue = self.state_condvar.wait(queue).unwrap();
+        }
+    }
+}
+
+impl Default for ListenQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ListenQueue {
+    pub fn new() -> Self {
+        Self {
+            syn_queue: HashMap::new(),
+            accept_queue: VecDeque::new(),
+            state_condvar: Arc::new(Condvar::new()),
+            cookies: SynCookies::new(),
+            cookie_instant: None,
+            ts_offset: rand::random(),
+            ts_clock: std::time::Instant::now(),
+        }
+    }
+
+    pub fn state_condvar(&self) -> Arc<Condvar> {
+        Arc::clone(&self.state_condvar)
+    }
+
+    /// creates a TCB for a SYN received on the listening port, or for the ACK
+    /// of a SYN cookie, the caller is responsible for tracking the returned socket
+    pub fn on_segment(
+        &mut self,
+        quad: Quad,
+        pkt: etherparse::TcpSlice,
+        tx: mpsc::Sender<Vec<u8>>,
+        challenge_acks: Arc<ChallengeAckLimiter>,
+        isn: &dyn IsnGenerator,
+        mtu: u16,
+    ) -> Option<Arc<Mutex<TcpSocket>>> {
+        if pkt.rst() {
+            debug!(?quad, "received RST for listening port, dropping");
+            return None;
+        }
+
+        if pkt.ack() && !pkt.syn() {
+            return self.on_syn_cookie_ack(quad, pkt, tx, challenge_acks, mtu);
+        }
+
+        // RFC 9293 3.10.7.2, any other acknowledgment is bad, the peer might
+        // still hold a half-open connection we forgot about
+        if pkt.ack() {
+            warn!(?quad, "received ACK for listening port, sending RST");
+            transmit_rst(quad, &pkt, &tx);
+            return None;
+        }
+
+        if !pkt.syn() {
+            warn!(
+                ?quad,
+                "received non-SYN packet for listening port, dropping"
+            );
+            return None;
+        }
+
+        // half-open connections that gave up don't count against the backlog
+        self.syn_queue.retain(|_, socket| {
+            socket
+                .upgrade()
+                .is_some_and(|socket| !socket.lock().unwrap().is_closed())
+        });
+
+        if self.syn_queue.len() >= MAX_SYN_BACKLOG {
+            // RFC 4987 3.6, answer without keeping any state, the connection
+            // is rebuilt from the ACK of the cookie
+            debug!(?quad, "SYN queue is full, sending SYN cookie");
+            self.transmit_syn_cookie(quad, &pkt, &tx, mtu);
+            self.cookie_instant = Some(std::time::Instant::now());
+            return None;
+        }
+
+        let mut socket = TcpSocket::new(quad.0, quad.1, isn.isn(&quad), tx, challenge_acks, mtu);
+        socket.on_packet(pkt);
+
+        let socket = Arc::new(Mutex::new(socket));
+        self.syn_queue.insert(quad, Arc::downgrade(&socket));
+        Some(socket)
+    }
+
+    /// sends a SYN-ACK whose ISS is a SYN cookie, the options the connection
+    /// is rebuilt with are encoded in it and in the TSval
+    fn transmit_syn_cookie(
+        &self,
+        quad: Quad,
+        pkt: &etherparse::TcpSlice,
+        tx: &mpsc::Sender<Vec<u8>>,
+        mtu: u16,
+    ) {
+        let peer = SynOptions::parse(pkt);
+        let (cookie, _) = self
+            .cookies
+            .generate(&quad, pkt.sequence_number(), peer.mss);
+
+        let mut header = etherparse::TcpHeader::new(quad.0.port(), quad.1.port(), cookie, 0xFFFF);
+        header.acknowledgment_number = pkt.sequence_number().wrapping_add(1);
+        header.syn = true;
+        header.ack = true;
+
+        let ts_now = self
+            .ts_offset
+            .wrapping_add(self.ts_clock.elapsed().as_millis() as u32);
+        let timestamp = peer.timestamp.map(|(ts_val, _)| {
+            let options = syncookie::encode_options(ts_now, peer.window_scale, peer.sack_permitted);
+            (options, ts_val)
+        });
+        // SACK and window scaling only survive in the timestamp, so they
+        // aren't offered without one
+        let options = SynOptions {
+            mss: local_mss(mtu),
+            sack_permitted: peer.sack_permitted && timestamp.is_some(),
+            window_scale: peer.window_scale.and(timestamp).map(|_| RECV_WINDOW_SCALE),
+            timestamp,
+        };
+
+        header.set_options(&options.elements()).unwrap();
+        transmit_header(quad, header, tx);
+    }
+
+    /// creates the TCB for the ACK completing a handshake answered with a SYN
+    /// cookie, as long as the cookie checks out
+    fn on_syn_cookie_ack(
+        &mut self,
+        quad: Quad,
+        pkt: etherparse::TcpSlice,
+        tx: mpsc::Sender<Vec<u8>>,
+        challenge_acks: Arc<ChallengeAckLimiter>,
+        mtu: u16,
+    ) -> Option<Arc<Mutex<TcpSocket>>> {
+        // without a recent overflow there are no cookies to accept, and
+        // attackers can't take their time guessing one
+        if self
+            .cookie_instant
+            .is_none_or(|instant| instant.elapsed() >= syncookie::LIFETIME)
+        {
+            warn!(?quad, "received ACK for listening port, sending RST");
+            transmit_rst(quad, &pkt, &tx);
+            return None;
+        }
+
+        let Some(mss) =
+            self.cookies
+                .check(&quad, pkt.sequence_number(), pkt.acknowledgment_number())
+        else {
+            warn!(?quad, "received ACK with invalid SYN cookie, sending RST");
+            transmit_rst(quad, &pkt, &tx);
+            return None;
+        };
+
+        // the other options only survive in the echoed timestamp
+        let (window_scale, sack_permitted) = match timestamp_option(&pkt) {
+            Some((_, ts_ecr)) => match syncookie::decode_options(ts_ecr) {
+                Some(options) => options,
+                None => {
+                    warn!(
+                        ?quad,
+                        "received ACK with invalid SYN cookie options, dropping"
+                    );
+                    return None;
+                }
+            },
+            None => (None, false),
+        };
+
+        let iss = pkt.acknowledgment_number().wrapping_sub(1);
+        let mut socket = TcpSocket::new(quad.0, quad.1, iss, tx, challenge_acks, mtu);
+        socket.on_syn_cookie_ack(pkt, mss, window_scale, sack_permitted);
+
+        debug!(
+            ?quad,
+            "SYN cookie accepted, connection ready to be accepted"
+        );
+        let socket = Arc::new(Mutex::new(socket));
+        self.accept_queue.push_back((quad.1, Arc::clone(&socket)));
+        self.state_condvar.notify_all();
+
+        Some(socket)
+    }
+
+    /// moves the connection to the accept queue once it leaves SYN-RECEIVED
+    pub fn on_packet(&mut self, quad: Quad, socket: &Arc<Mutex<TcpSocket>>) {
+        if !self.syn_queue.contains_key(&quad) {
+            return;
+        }
+
+        match socket.lock().unwrap().state {
+            TcpState::SynReceived => return,
+            TcpState::Closed => {
+                debug!(?quad, "half-open connection was closed");
+            }
+            _ => {
+                debug!(?quad, "connection ready to be accepted");
+                self.accept_queue.push_back((quad.1, Arc::clone(socket)));
+                self.state_condvar.notify_all();
+            }
+        }
+
+        self.syn_queue.remove(&quad);
+    }
+}
+
 impl TcpSocket {
+    /// `iss` is the initial send sequence number, see `IsnGenerator`
     pub fn new(
         source_addr: SocketAddrV4,
         destination_addr: SocketAddrV4,
+        iss: u32,
         tx: mpsc::Sender<Vec<u8>>,
+        challenge_acks: Arc<ChallengeAckLimiter>,
+        mtu: u16,
     ) -> Self {
-        let sequence_number = rand::random();
+        let local_mss = local_mss(mtu);
 
         Self {
             source_ip: source_addr.ip().octets(),
             destination_ip: destination_addr.ip().octets(),
-            send_unack: sequence_number,
-            send_next: sequence_number + 1,
+            send_unack: iss,
+            send_next: iss.wrapping_add(1),
+            send_queued: iss.wrapping_add(1),
+            send_max: iss.wrapping_add(1),
+            send_wnd: 0,
+            send_wl1: 0,
+            send_wl2: 0,
+            max_send_wnd: 0,
             recv_next: 0,
+            recv_edge: 0,
             srtt: 0.0,
             rttvar: 0.0,
             rto: 1.0,
-            syn_seq: sequence_number,
+            syn_seq: iss,
             fin_seq: None,
-            send_window: Vec::new(),
-            recv_window: Vec::new(),
+            local_mss,
+            send_mss: DEFAULT_MSS.min(local_mss),
+            sack_permitted: false,
+            sack_blocks: Vec::new(),
+            dsack_block: None,
+            window_scaling: false,
+            send_wscale: 0,
+            recv_wscale: 0,
+            ts_enabled: false,
+            ts_recent: 0,
+            ts_recent_instant: std::time::Instant::now(),
+            ts_offset: rand::random(),
+            ts_clock: std::time::Instant::now(),
+            last_ack_sent: 0,
+            scoreboard: Scoreboard::default(),
+            high_rxt: iss,
+            nodelay: false,
+            ack_delay: DEFAULT_ACK_DELAY,
+            delayed_ack: None,
+            delayed_segments: 0,
+            sws_timer: None,
+            persist_instant: None,
+            persist_backoff: 0,
+            persist_probes: 0,
+            congestion_algorithm: CongestionAlgorithm::default(),
+            congestion: CongestionAlgorithm::default().build(DEFAULT_MSS.min(local_mss)),
+            dup_acks: 0,
+            recover: iss,
+            recovery_cwnd: None,
+            undo_marker: None,
+            undo_retrans: 0,
+            retransmits: 0,
+            spurious_retransmits: 0,
+            delivered: 0,
+            delivered_time: std::time::Instant::now(),
+            first_sent_time: std::time::Instant::now(),
+            app_limited_until: 0,
+            pacing_budget: 0.0,
+            pacing_instant: std::time::Instant::now(),
+            send_buffer: vec![0; SEND_BUFFER_SIZE],
+            recv_window: VecDeque::new(),
             header: etherparse::TcpHeader {
                 source_port: source_addr.port(),
                 destination_port: destination_addr.port(),
-                sequence_number,
+                sequence_number: iss,
                 acknowledgment_number: 0,
                 ns: false,
                 fin: false,
@@ -158,9 +1110,25 @@ impl TcpSocket {
             state: TcpState::Listen,
             state_condvar: Arc::new(Condvar::new()),
             tx,
+            challenge_acks,
             partial_segments: BTreeMap::new(),
             timers: BTreeMap::new(),
             time_wait_instant: None,
+            keepalive: None,
+            keepalive_probes: 0,
+            last_recv_instant: std::time::Instant::now(),
+            error: None,
+            syn_limits: RetransmitLimits::SYN,
+            data_limits: RetransmitLimits::DATA,
+            timeouts: 0,
+            first_timeout_instant: None,
+            user_timeout: None,
+            advertise_user_timeout: false,
+            user_timeout_pending: false,
+            peer_user_timeout: None,
+            unacked_since: None,
+            read_shutdown: false,
+            fin_deferred: false,
         }
     }
 
@@ -168,11 +1136,66 @@ impl TcpSocket {
         Arc::clone(&self.state_condvar)
     }
 
+    /// rebuilds the TCB from the ACK completing a handshake that was answered
+    /// with a SYN cookie, and the options the cookie encoded
+    fn on_syn_cookie_ack(
+        &mut self,
+        pkt: etherparse::TcpSlice,
+        mss: u16,
+        window_scale: Option<u8>,
+        sack_permitted: bool,
+    ) {
+        self.send_unack = pkt.acknowledgment_number();
+        self.recv_next = pkt.sequence_number();
+        self.recv_edge = self.recv_next;
+        self.last_ack_sent = self.recv_next;
+
+        self.send_mss = mss.min(self.local_mss);
+        self.congestion = self.congestion_algorithm.build(self.send_mss);
+        self.sack_permitted = sack_permitted;
+        self.window_scaling = window_scale.is_some();
+        if let Some(shift) = window_scale {
+            self.send_wscale = shift;
+            self.recv_wscale = RECV_WINDOW_SCALE;
+        }
+        if let Some((ts_val, ts_ecr)) = timestamp_option(&pkt) {
+            self.ts_enabled = true;
+            self.ts_recent = ts_val;
+            self.ts_recent_instant = std::time::Instant::now();
+            // our clock carries on from the TSval the cookie was sent with
+            self.ts_offset = ts_ecr;
+            self.ts_clock = std::time::Instant::now();
+        }
+        debug!(
+            send_mss = self.send_mss,
+            sack_permitted,
+            send_wscale = self.send_wscale,
+            recv_wscale = self.recv_wscale,
+            timestamps = self.ts_enabled,
+            "options restored from SYN cookie"
+        );
+
+        self.header.sequence_number = self.send_next;
+        self.header.acknowledgment_number = self.recv_next;
+        self.header.ack = true;
+        self.update_recv_window();
+        self.set_send_window(&pkt);
+        self.set_state(TcpState::Established);
+
+        if !pkt.payload().is_empty() || pkt.fin() {
+            self.on_packet(pkt);
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(self.state, TcpState::Closed)
+    }
+
     pub fn connect(&mut self) {
         self.header.syn = true;
+        self.set_syn_options();
         self.state = TcpState::SynSent;
-        self.timers
-            .insert(self.syn_seq, (false, std::time::Instant::now()));
+        self.track_segment(self.syn_seq, false);
         self.transmit_payload(self.header.clone(), &[]).unwrap();
     }
 
@@ -186,40 +1209,103 @@ impl TcpSocket {
             rseq,
             snd.una = self.send_unack,
             snd.nxt = self.send_next,
+            cwnd = self.congestion.cwnd(),
             rcv.nxt = self.recv_next,
             fin_seq = self.fin_seq
         )
     }
 
-    fn set_state(&mut self, state: TcpState) {
-        info!("transitioned to {state:?}");
-
-        match state {
-            TcpState::TimeWait => self.time_wait_instant = Some(std::time::Instant::now()),
-            _ => {}
+    /// options that are only sent on SYN segments
+    fn set_syn_options(&mut self) {
+        let syn_ack = self.header.ack;
+        let options = SynOptions {
+            mss: self.local_mss,
+            // a SYN-ACK can only offer SACK if the SYN did (RFC 2018 2)
+            sack_permitted: !syn_ack || self.sack_permitted,
+            // same for window scaling (RFC 7323 2.2)
+            window_scale: (!syn_ack || self.window_scaling).then_some(RECV_WINDOW_SCALE),
+            // and timestamps, TSecr is only valid on the SYN-ACK (RFC 7323 3.2)
+            timestamp: (!syn_ack || self.ts_enabled)
+                .then(|| (self.ts_now(), if syn_ack { self.ts_recent } else { 0 })),
         };
 
-        self.state = state;
-        self.state_condvar.notify_all();
+        self.header.set_options(&options.elements()).unwrap();
+
+        // RFC 5482 3, the user timeout is announced from the start
+        if let Some(timeout) = self.user_timeout.filter(|_| self.advertise_user_timeout) {
+            if let Some(options) = with_user_timeout_option(&self.header.options, timeout) {
+                self.header.options = options;
+            }
+        }
+    }
+
+    fn on_syn_options(&mut self, pkt: &etherparse::TcpSlice) {
+        let options = SynOptions::parse(pkt);
+
+        self.send_mss = options.mss.min(self.local_mss);
+        self.congestion = self.congestion_algorithm.build(self.send_mss);
+        self.sack_permitted = options.sack_permitted;
+        self.window_scaling = options.window_scale.is_some();
+        if let Some(shift) = options.window_scale {
+            self.send_wscale = shift;
+            self.recv_wscale = RECV_WINDOW_SCALE;
+        }
+        self.peer_user_timeout = user_timeout_option(pkt);
+        self.ts_enabled = options.timestamp.is_some();
+        if let Some((ts_val, _)) = options.timestamp {
+            self.ts_recent = ts_val;
+            self.ts_recent_instant = std::time::Instant::now();
+        }
+        debug!(
+            peer_mss = options.mss,
+            send_mss = self.send_mss,
+            sack_permitted = self.sack_permitted,
+            send_wscale = self.send_wscale,
+            recv_wscale = self.recv_wscale,
+            timestamps = self.ts_enabled,
+            "negotiated options"
+        );
+    }
+
+    fn set_state(&mut self, state: TcpState) {
+        info!("transitioned to {state:?}");
+
+        if let TcpState::TimeWait = state {
+            self.time_wait_instant = Some(std::time::Instant::now());
+        }
+
+        self.state = state;
+        self.state_condvar.notify_all();
     }
 
     // RFC 6298
-    fn on_rtt_measurement(&mut self, ack: u32) {
-        let r = if let Some((_, (retransmitted, instant))) = self
+    fn on_rtt_measurement(&mut self, ack: u32, ts_ecr: Option<u32>) {
+        let segment = self
             .timers
             .iter()
-            .filter(|(seq, _)| self.send_unack <= **seq && **seq < ack)
-            .nth(0)
-        {
-            if *retransmitted {
+            .filter(|(seq, _)| seq_le(self.send_unack, **seq) && seq_lt(**seq, ack))
+            .min_by_key(|(seq, _)| seq.wrapping_sub(self.send_unack))
+            .map(|(_, segment)| segment);
+
+        let r = match (segment, ts_ecr) {
+            (Some(segment), _) if !segment.retransmitted => {
+                std::time::Instant::now().duration_since(segment.sent)
+            }
+            // RFC 7323 4, the echoed timestamp belongs to the transmission that
+            // arrived, so retransmitted segments can be measured too
+            (_, Some(ts_ecr))
+                if self.ts_enabled && self.ts_now().wrapping_sub(ts_ecr) as i32 >= 0 =>
+            {
+                std::time::Duration::from_millis(self.ts_now().wrapping_sub(ts_ecr) as u64)
+            }
+            (Some(_), _) => {
                 debug!(ack, "segment was retransmitted, not measuring RTT");
                 return;
             }
-
-            std::time::Instant::now().duration_since(*instant)
-        } else {
-            error!(ack, "segment did not exist in retransmission queue");
-            return;
+            (None, _) => {
+                error!(ack, "segment did not exist in retransmission queue");
+                return;
+            }
         };
 
         // reset the measurements if RTO was multiplied for retransmission
@@ -232,6 +1318,7 @@ impl TcpSocket {
         }
 
         self.rto = (self.srtt + (4.0 * self.rttvar).max(0.01)).max(1.0);
+        self.congestion.on_rtt_sample(r);
     }
 
     /// returns whether the socket can be cleaned up
@@ -239,67 +1326,167 @@ impl TcpSocket {
         let span = self.get_span(None);
         let _enter = span.enter();
 
-        self.timers.retain(|seq, _| *seq >= self.send_unack);
-        if let Some(mut entry) = self.timers.first_entry() {
-            let (seq, (retransmitted, instant)) = (entry.key().clone(), entry.get_mut());
+        // a closed connection has nothing left to send
+        if let TcpState::Closed = self.state {
+            return false;
+        }
+
+        self.timers.retain(|seq, _| seq_le(self.send_unack, *seq));
+
+        if let Some(instant) = self.delayed_ack {
+            if instant.elapsed() >= self.ack_delay {
+                debug!("delayed ACK timer expired");
+                self.transmit_ack();
+            }
+        }
+
+        if let Some(instant) = self.sws_timer {
+            if std::time::Instant::now().duration_since(instant) >= SWS_OVERRIDE_TIMEOUT {
+                debug!("SWS override timer expired, sending partial segment");
+                self.transmit_queued(true);
+            }
+        }
+
+        // RFC 9293 3.8.6.1, probe the zero window with exponential backoff
+        if let Some(instant) = self.persist_instant {
+            let timeout = (self.rto * 2.0_f64.powi(self.persist_backoff as i32)).min(60.0);
+            if std::time::Instant::now()
+                .duration_since(instant)
+                .as_secs_f64()
+                >= timeout
+            {
+                if self.persist_probes >= MAX_PERSIST_PROBES {
+                    warn!("zero window probes went unanswered, aborting");
+                    self.abort(std::io::ErrorKind::TimedOut);
+                    return false;
+                }
+
+                debug!(probes = self.persist_probes, "sending zero window probe");
+                self.persist_instant = Some(std::time::Instant::now());
+                self.persist_backoff += 1;
+                self.persist_probes += 1;
+                self.transmit_window_probe();
+            }
+        }
+
+        // paced data might be due
+        if self.congestion.pacing_rate().is_some() {
+            self.transmit_queued(false);
+        }
+
+        // RFC 1122 4.2.3.6, probe idle connections, outstanding data is covered
+        // by the retransmission and persist timers instead
+        if let Some(keepalive) = self.keepalive {
+            if matches!(self.state, TcpState::Established | TcpState::CloseWait)
+                && self.send_max == self.send_unack
+                && self.persist_instant.is_none()
+                && self.last_recv_instant.elapsed()
+                    >= keepalive.idle + keepalive.interval * self.keepalive_probes
+            {
+                if self.keepalive_probes >= keepalive.count {
+                    warn!("keepalive probes went unanswered, timing out");
+                    self.abort(std::io::ErrorKind::TimedOut);
+                    return false;
+                }
+
+                debug!(probes = self.keepalive_probes, "sending keepalive probe");
+                self.keepalive_probes += 1;
+                self.transmit_keepalive();
+            }
+        }
+
+        // RFC 5482, the user bounds how long data can go unacknowledged
+        if self
+            .user_timeout
+            .zip(self.unacked_since)
+            .is_some_and(|(timeout, instant)| instant.elapsed() >= timeout)
+        {
+            warn!("data went unacknowledged past the user timeout, timing out");
+            self.abort(std::io::ErrorKind::TimedOut);
+            return false;
+        }
+
+        // RFC 1122 4.2.3.5, the peer is considered dead once the segment at
+        // SND.UNA was retransmitted too often or for too long
+        let limits = match self.state {
+            TcpState::SynSent | TcpState::SynReceived => self.syn_limits,
+            _ => self.data_limits,
+        };
+        let rto_expired = self
+            .first_timer()
+            .and_then(|seq| self.timers.get(&seq))
+            .is_some_and(|segment| segment.sent.elapsed().as_secs_f64() >= self.rto);
+        if (rto_expired && self.timeouts >= limits.r2)
+            || self
+                .first_timeout_instant
+                .is_some_and(|instant| instant.elapsed() >= limits.timeout)
+        {
+            warn!(
+                timeouts = self.timeouts,
+                "retransmissions went unanswered, timing out"
+            );
+            self.abort(std::io::ErrorKind::TimedOut);
+            return false;
+        }
+
+        if let Some(seq) = self.first_timer() {
+            let segment = self.timers.get_mut(&seq).unwrap();
             if std::time::Instant::now()
-                .duration_since(*instant)
+                .duration_since(segment.sent)
                 .as_secs_f64()
                 >= self.rto
             {
                 debug!(
                     seq,
-                    retransmitted = *retransmitted,
+                    retransmitted = segment.retransmitted,
                     "retransmitting segment"
                 );
 
-                *instant = std::time::Instant::now();
-                *retransmitted = true;
+                segment.sent = std::time::Instant::now();
+                segment.retransmitted = true;
                 self.rto = (self.rto * 2.0).min(60.0);
 
-                if seq == self.syn_seq {
-                    self.transmit_payload(self.header.clone(), &[]).unwrap();
-                    return false;
+                self.timeouts += 1;
+                self.first_timeout_instant
+                    .get_or_insert_with(std::time::Instant::now);
+                if self.timeouts == limits.r1 {
+                    warn!(
+                        timeouts = self.timeouts,
+                        "reached R1, the path may be failing"
+                    );
                 }
 
-                let mut header = self.header.clone();
-                header.sequence_number = seq;
-
-                if let Some(fin_seq) = self.fin_seq {
-                    if seq == fin_seq {
-                        self.transmit_payload(self.header.clone(), &[]).unwrap();
-                        return false;
-                    }
+                // after 2^32 bytes data can start at the ISS again, only the
+                // handshake retransmits the SYN
+                if matches!(self.state, TcpState::SynSent | TcpState::SynReceived) {
+                    self.set_syn_options();
+                    self.transmit_payload(self.header.clone(), &[]).unwrap();
+                    return false;
                 }
 
-                let len = self.send_window.len();
-                let begin = seq as usize % len;
-                let end = self.send_next as usize % len;
-
-                header.psh = true;
+                // RFC 5681 3.1, collapse the congestion window and go back to
+                // SND.UNA, everything outstanding is resent as the window grows
+                // again, or probed for if the peer's window is closed
+                self.congestion
+                    .on_rto(self.send_max.wrapping_sub(self.send_unack));
+                self.undo_marker = Some(self.send_unack);
+                self.undo_retrans = 0;
+                debug!(
+                    cwnd = self.congestion.cwnd(),
+                    ssthresh = self.congestion.ssthresh(),
+                    "congestion window collapsed"
+                );
 
-                // TODO respect MSS
-                // TODO either re-transmit all segments in the order they were created
-                // or remove redundant timers here as we send a larger payload
-                if begin <= end {
-                    self.transmit_payload(header, &self.send_window[begin..end])
-                        .unwrap()
-                } else {
-                    let mut payload = Vec::with_capacity(self.send_window.len() - (begin - end));
-                    payload.extend_from_slice(&self.send_window[begin..self.send_window.len()]);
-                    payload.extend_from_slice(&self.send_window[0..end]);
-                    self.transmit_payload(header, &payload).unwrap();
-                }
-            }
-        } else if let TcpState::FinWait1 = self.state {
-            // all queues are clear, we can close
-            if self.fin_seq.is_none() {
-                info!("all pending segments retransmitted, sending FIN");
+                // data sent before the timeout can't trigger another fast retransmit
+                self.recover = self.send_max;
+                self.recovery_cwnd = None;
+                self.dup_acks = 0;
+                // the peer may have discarded SACKed data (RFC 2018 8)
+                self.scoreboard.clear();
 
-                self.header.fin = true;
-                self.header.sequence_number = self.send_next;
-                self.fin_seq = Some(self.header.sequence_number);
-                self.transmit_payload(self.header.clone(), &[]).unwrap();
+                self.timers.clear();
+                self.send_next = self.send_unack;
+                self.transmit_queued(false);
             }
         } else if let Some(time_wait_instant) = self.time_wait_instant {
             // we take MSL as 30s
@@ -313,7 +1500,7 @@ impl TcpSocket {
             }
         }
 
-        return false;
+        false
     }
 
     pub fn on_packet(&mut self, pkt: etherparse::TcpSlice) {
@@ -323,16 +1510,129 @@ impl TcpSocket {
         //info!("received packet {:?}", pkt);
 
         match self.state {
-            TcpState::Listen | TcpState::SynReceived => todo!("listen"),
+            TcpState::Listen => {
+                // the listen queue only hands over SYNs
+                if pkt.rst() || pkt.ack() || !pkt.syn() {
+                    return;
+                }
+
+                info!("received SYN, sending SYN-ACK");
+
+                self.recv_next = pkt.sequence_number().wrapping_add(1);
+                self.recv_edge = self.recv_next;
+                self.update_recv_window();
+                self.on_syn_options(&pkt);
+
+                self.header.acknowledgment_number = self.recv_next;
+                self.header.syn = true;
+                self.header.ack = true;
+                self.set_syn_options();
+
+                self.track_segment(self.syn_seq, false);
+                self.set_state(TcpState::SynReceived);
+                self.transmit_payload(self.header.clone(), &[]).unwrap();
+            }
+            TcpState::SynReceived => {
+                if pkt.rst() {
+                    if pkt.sequence_number() == self.recv_next {
+                        info!("received RST, closing");
+                        // fails a simultaneous open like a refused connection
+                        self.error = Some(std::io::ErrorKind::ConnectionRefused);
+                        self.set_state(TcpState::Closed);
+                    }
+
+                    return;
+                }
+
+                if pkt.syn() && !pkt.ack() {
+                    debug!("received retransmitted SYN, re-sending SYN-ACK");
+                    self.set_syn_options();
+                    self.transmit_payload(self.header.clone(), &[]).unwrap();
+                    return;
+                }
+
+                if !pkt.ack() {
+                    warn!("received segment without ACK, dropping");
+                    return;
+                }
+
+                if pkt.acknowledgment_number() != self.send_next {
+                    error!("invalid ACK, sending RST");
+
+                    let mut header = self.header.clone();
+                    header.syn = false;
+                    header.ack = false;
+                    header.rst = true;
+                    header.sequence_number = pkt.acknowledgment_number();
+                    self.transmit_payload(header, &[]).unwrap();
+
+                    return;
+                }
+
+                info!("received ACK for SYN-ACK");
+
+                self.on_rtt_measurement(
+                    pkt.acknowledgment_number(),
+                    timestamp_option(&pkt).map(|(_, ts_ecr)| ts_ecr),
+                );
+
+                self.send_unack = pkt.acknowledgment_number();
+                self.timeouts = 0;
+                self.first_timeout_instant = None;
+                self.unacked_since =
+                    (self.send_unack != self.send_max).then(std::time::Instant::now);
+                self.header.sequence_number = self.send_next;
+                self.header.syn = false;
+                self.header.options = etherparse::TcpOptions::default();
+
+                // the window in the SYN-ACK wasn't scaled
+                self.update_recv_window();
+                self.set_send_window(&pkt);
+
+                self.set_state(TcpState::Established);
+
+                if self.fin_deferred {
+                    self.fin_deferred = false;
+                    self.set_state(TcpState::FinWait1);
+                    self.queue_fin();
+                }
+
+                // the ACK completing the handshake can carry data or a FIN
+                if !pkt.payload().is_empty() || pkt.fin() {
+                    self.on_packet(pkt);
+                }
+            }
             TcpState::SynSent => {
                 if !pkt.ack() {
-                    error!("don't know how to handle packet without ACK bit");
+                    if pkt.syn() && !pkt.rst() {
+                        // RFC 9293 3.10.7.3, simultaneous open, our SYN crossed
+                        // the peer's, which still has to acknowledge ours
+                        info!("received SYN, sending SYN-ACK");
+
+                        self.recv_next = pkt.sequence_number().wrapping_add(1);
+                        self.recv_edge = self.recv_next;
+                        self.update_recv_window();
+                        self.on_syn_options(&pkt);
+                        self.set_send_window(&pkt);
+
+                        self.header.acknowledgment_number = self.recv_next;
+                        self.header.ack = true;
+                        self.set_syn_options();
+
+                        self.set_state(TcpState::SynReceived);
+                        self.transmit_payload(self.header.clone(), &[]).unwrap();
+                    }
+
                     return;
                 }
 
                 if pkt.rst() {
-                    info!("received RST, closing");
-                    self.set_state(TcpState::Closed);
+                    // RFC 9293 3.10.7.3, only a RST acknowledging our SYN is acceptable
+                    if pkt.acknowledgment_number() == self.send_next {
+                        info!("received RST, connection refused");
+                        self.error = Some(std::io::ErrorKind::ConnectionRefused);
+                        self.set_state(TcpState::Closed);
+                    }
 
                     return;
                 }
@@ -345,8 +1645,6 @@ impl TcpSocket {
                     header.ack = false;
                     header.rst = true;
                     header.sequence_number = pkt.acknowledgment_number();
-
-                    self.set_state(TcpState::CloseWait);
                     self.transmit_payload(header, &[]).unwrap();
 
                     return;
@@ -355,20 +1653,28 @@ impl TcpSocket {
                 if pkt.syn() {
                     info!("received SYN-ACK");
 
-                    self.on_rtt_measurement(pkt.acknowledgment_number());
+                    self.on_syn_options(&pkt);
+                    self.on_rtt_measurement(
+                        pkt.acknowledgment_number(),
+                        timestamp_option(&pkt).map(|(_, ts_ecr)| ts_ecr),
+                    );
 
                     self.recv_next = pkt.sequence_number().wrapping_add(1);
+                    self.recv_edge = self.recv_next;
                     self.send_unack = pkt.acknowledgment_number();
+                    self.timeouts = 0;
+                    self.first_timeout_instant = None;
+                    self.unacked_since =
+                        (self.send_unack != self.send_max).then(std::time::Instant::now);
+                    self.set_send_window(&pkt);
 
                     self.header.sequence_number = self.send_next;
                     self.header.acknowledgment_number = self.recv_next;
                     self.header.syn = false;
                     self.header.ack = true;
+                    self.header.options = etherparse::TcpOptions::default();
 
-                    self.header.window_size = pkt.window_size();
-                    self.send_window
-                        .reserve_exact(self.header.window_size as usize);
-                    self.send_window.resize(self.header.window_size as usize, 0);
+                    self.update_recv_window();
 
                     self.set_state(TcpState::Established);
                     self.transmit_payload(self.header.clone(), &[]).unwrap();
@@ -381,49 +1687,123 @@ impl TcpSocket {
             | TcpState::Closing
             | TcpState::LastAck
             | TcpState::TimeWait => {
-                let recv_seq_with_len = self.recv_next.wrapping_add(self.header.window_size as u32);
-                let seq_with_len = pkt
-                    .sequence_number()
-                    .wrapping_add(pkt.payload().len().max(1) as u32 - 1);
-                if !((self.recv_next <= pkt.sequence_number()
-                    && pkt.sequence_number() < recv_seq_with_len)
-                    || (self.recv_next <= seq_with_len && seq_with_len < recv_seq_with_len))
-                {
+                let timestamp = timestamp_option(&pkt);
+                if self.ts_enabled && timestamp.is_none() && !pkt.rst() {
+                    warn!("received segment without timestamp, dropping");
+                    return;
+                }
+
+                // RFC 7323 5.3 R1, PAWS, segments with a timestamp older than
+                // TS.Recent are old duplicates, unless the connection was idle
+                // for so long that the peer's clock could have wrapped
+                let paws_passed = match timestamp {
+                    Some((ts_val, _)) if self.ts_enabled && !pkt.rst() => {
+                        seq_le(self.ts_recent, ts_val)
+                            || self.ts_recent_instant.elapsed() >= PAWS_IDLE_TIMEOUT
+                    }
+                    _ => true,
+                };
+                if !paws_passed {
+                    warn!(
+                        ts_recent = self.ts_recent,
+                        "received segment with an old timestamp"
+                    );
+                }
+
+                // RFC 9293 3.10.7.4, segments are acceptable if they overlap the receive window
+                let recv_wnd = self.recv_wnd();
+                let seq = pkt.sequence_number();
+                let len = pkt.payload().len() as u32;
+                let in_window = |seq: u32| {
+                    seq_le(self.recv_next, seq)
+                        && seq_lt(seq, self.recv_next.wrapping_add(recv_wnd))
+                };
+                // a closed window takes no data, but the ACK and window of a
+                // segment at RCV.NXT are still processed
+                let drop_payload = len > 0 && recv_wnd == 0 && seq == self.recv_next;
+                let acceptable = paws_passed
+                    && match (len, recv_wnd) {
+                        (0, 0) => seq == self.recv_next,
+                        (0, _) => in_window(seq),
+                        (_, 0) => drop_payload,
+                        _ => in_window(seq) || in_window(seq.wrapping_add(len - 1)),
+                    };
+
+                if !acceptable {
+                    // RFC 9293 3.10.7.4, the peer didn't get the ACK for its FIN,
+                    // acknowledge it again and restart the 2MSL timeout
+                    if let (TcpState::TimeWait, true) = (&self.state, pkt.fin()) {
+                        self.time_wait_instant = Some(std::time::Instant::now());
+                    }
+
                     if !pkt.rst() {
                         warn!("received unacceptable segment, sending duplicate ACK");
-
-                        let mut header = self.header.clone();
-                        header.sequence_number = self.send_next;
-                        header.acknowledgment_number = self.recv_next;
-                        header.ack = true;
-                        self.transmit_payload(header, &[]).unwrap();
+                        if len > 0 && seq_le(seq.wrapping_add(len), self.recv_next) {
+                            self.dsack_block = Some((seq, seq.wrapping_add(len)));
+                        }
+                        self.transmit_ack();
                     } else {
-                        warn!("received unacceptable segment with RST, closing");
-                        self.set_state(TcpState::Closed);
+                        // RFC 5961 3.2, only RSTs in the window are considered
+                        debug!("received RST outside of the window, dropping");
                     }
 
                     return;
                 }
 
+                // the peer is alive, restart the keepalive timer
+                self.last_recv_instant = std::time::Instant::now();
+                self.keepalive_probes = 0;
+
+                if let Some(timeout) = user_timeout_option(&pkt) {
+                    if self.peer_user_timeout != Some(timeout) {
+                        debug!(?timeout, "peer advertised a new user timeout");
+                        self.peer_user_timeout = Some(timeout);
+                    }
+                }
+
+                // RFC 7323 4.3, only segments that cover the last ACK we sent
+                // update TS.Recent, so delayed ACKs echo the earliest timestamp
+                if let Some((ts_val, _)) = timestamp {
+                    if self.ts_enabled
+                        && seq_le(self.ts_recent, ts_val)
+                        && seq_le(seq, self.last_ack_sent)
+                    {
+                        self.ts_recent = ts_val;
+                        self.ts_recent_instant = std::time::Instant::now();
+                    }
+                }
+
                 if pkt.rst() {
                     if pkt.sequence_number() == self.recv_next {
                         debug!("received RST, closing");
+                        // RFC 9293 3.10.7.4, the connection is only reported as
+                        // reset to the user before the FIN exchange completes
+                        if matches!(
+                            self.state,
+                            TcpState::Established
+                                | TcpState::FinWait1
+                                | TcpState::FinWait2
+                                | TcpState::CloseWait
+                        ) {
+                            self.error = Some(std::io::ErrorKind::ConnectionReset);
+                        }
                         self.set_state(TcpState::Closed);
                     } else {
+                        // RFC 5961 3.2, the peer answers with an exact RST if it
+                        // really lost the connection
                         warn!("received RST with wrong seq, sending challenge ACK");
-                        // challenge ACK (RFC 5961)
-                        let mut header = self.header.clone();
-                        header.sequence_number = self.send_next;
-                        header.acknowledgment_number = self.recv_next;
-                        header.ack = true;
-                        self.transmit_payload(header, &[]).unwrap();
+                        self.transmit_challenge_ack();
                     }
 
                     return;
                 }
 
                 if pkt.syn() {
-                    todo!("can't handle SYN packets in state {:?}", self.state);
+                    // RFC 5961 4.2, a peer that restarted replies with a RST
+                    // carrying the right sequence number
+                    warn!("received SYN in synchronized state, sending challenge ACK");
+                    self.transmit_challenge_ack();
+                    return;
                 }
 
                 if !pkt.ack() {
@@ -431,19 +1811,82 @@ impl TcpSocket {
                     return;
                 }
 
-                if self.send_unack < pkt.acknowledgment_number()
-                    && pkt.acknowledgment_number() <= self.send_next
+                if self.persist_instant.is_some()
+                    && self.send_queued != self.send_next
+                    && self.send_next == self.send_max
+                    && pkt.acknowledgment_number() == self.send_next.wrapping_add(1)
+                {
+                    debug!("zero window probe was accepted");
+                    // probes aren't used for RTT measurements
+                    self.track_segment(self.send_next, true);
+                    self.send_next = self.send_next.wrapping_add(1);
+                    self.send_max = self.send_next;
+                }
+
+                // RFC 5961 5.2, ACKs for data never sent or older than the
+                // largest window the peer offered are likely to be forged
+                let ack = pkt.acknowledgment_number();
+                if seq_lt(self.send_max, ack)
+                    || seq_lt(ack, self.send_unack.wrapping_sub(self.max_send_wnd))
+                {
+                    warn!(ack, "received unacceptable ACK, sending challenge ACK");
+                    self.transmit_challenge_ack();
+                    return;
+                }
+
+                let newly_sacked = self.sack_permitted && self.on_sack_options(&pkt);
+
+                // RFC 5681 2, the window has to be checked before it is updated,
+                // RFC 6675 2 also counts ACKs that SACK new data
+                if pkt.acknowledgment_number() == self.send_unack
+                    && self.send_max != self.send_unack
+                    && (newly_sacked
+                        || (pkt.payload().is_empty()
+                            && !pkt.fin()
+                            && self.peer_window(&pkt) == self.send_wnd))
+                {
+                    self.on_duplicate_ack();
+                } else if seq_lt(self.send_unack, pkt.acknowledgment_number())
+                    && seq_le(pkt.acknowledgment_number(), self.send_max)
                 {
-                    self.on_rtt_measurement(pkt.acknowledgment_number());
+                    self.on_rtt_measurement(
+                        pkt.acknowledgment_number(),
+                        timestamp.map(|(_, ts_ecr)| ts_ecr),
+                    );
+                    self.on_delivery(pkt.acknowledgment_number());
                     debug!("advancing SND.UNA");
+                    let acked = pkt.acknowledgment_number().wrapping_sub(self.send_unack);
                     self.send_unack = pkt.acknowledgment_number();
+                    self.scoreboard.advance(self.send_unack);
+                    self.dup_acks = 0;
+                    self.timeouts = 0;
+                    self.first_timeout_instant = None;
+                    self.unacked_since =
+                        (self.send_unack != self.send_max).then(std::time::Instant::now);
+
+                    // after going back to SND.UNA the peer can acknowledge data
+                    // that wasn't resent yet
+                    if seq_lt(self.send_next, self.send_unack) {
+                        self.send_next = self.send_unack;
+                    }
+
+                    self.on_new_ack(acked);
                 }
 
-                let fin_acked = if let Some(seq) = self.fin_seq {
-                    self.send_unack == seq
-                } else {
-                    false
-                };
+                // RFC 9293 3.10.7.4, only take the window from segments that
+                // are newer than the ones used for the last update
+                if seq_le(self.send_unack, pkt.acknowledgment_number())
+                    && seq_le(pkt.acknowledgment_number(), self.send_max)
+                    && (seq_lt(self.send_wl1, pkt.sequence_number())
+                        || (self.send_wl1 == pkt.sequence_number()
+                            && seq_le(self.send_wl2, pkt.acknowledgment_number())))
+                {
+                    self.set_send_window(&pkt);
+                }
+
+                // the FIN takes up a sequence number, it's acknowledged once
+                // SND.UNA moves past it
+                let fin_acked = self.fin_seq.is_some_and(|seq| seq_lt(seq, self.send_unack));
 
                 if fin_acked {
                     debug!("FIN is acked");
@@ -456,71 +1899,129 @@ impl TcpSocket {
                             self.set_state(TcpState::Closed);
                             return;
                         }
-                        TcpState::TimeWait => {
-                            self.set_state(TcpState::TimeWait);
-                            let mut header = self.header.clone();
-                            header.acknowledgment_number = pkt.sequence_number();
-                            header.ack = true;
-                            self.transmit_payload(header, &[]).unwrap();
-                        }
                         _ => {}
                     }
                 }
 
-                // TODO update SND.WND
-                if !pkt.payload().is_empty() {
+                // the peer is alive, stop probing if the window opened up
+                self.persist_probes = 0;
+                if self.send_wnd > 0 {
+                    self.persist_instant = None;
+                    self.persist_backoff = 0;
+                }
+
+                // data held back by Nagle's algorithm or SWS avoidance might fit now
+                self.transmit_queued(false);
+
+                if drop_payload {
+                    debug!("receive window is closed, dropping payload");
+                    self.transmit_ack();
+                } else if !pkt.payload().is_empty() {
                     if let TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2 =
                         self.state
                     {
-                        if pkt.sequence_number() == self.recv_next {
+                        // the head of the segment was already received, report it
+                        // and keep the rest
+                        let (seq, payload) = if seq_lt(seq, self.recv_next) {
+                            self.dsack_block = Some((seq, self.recv_next));
+                            let offset = self.recv_next.wrapping_sub(seq) as usize;
+                            (self.recv_next, &pkt.payload()[offset..])
+                        } else {
+                            (seq, pkt.payload())
+                        };
+
+                        // RFC 5681 4.2, out-of-order segments and the ones
+                        // filling a gap are acknowledged right away
+                        let mut ack_now = true;
+
+                        if seq == self.recv_next {
                             debug!("received in-order segment");
 
-                            self.recv_window.extend_from_slice(pkt.payload());
-                            self.recv_next =
-                                self.recv_next.wrapping_add(pkt.payload().len() as u32);
+                            ack_now = !self.partial_segments.is_empty();
+
+                            self.recv_window.extend(payload);
+                            self.recv_next = self.recv_next.wrapping_add(payload.len() as u32);
+
+                            // buffered segments starting inside the delivered data
+                            // are trimmed at RCV.NXT and appended, the peer might
+                            // have re-segmented its retransmissions
+                            while let Some(start) = self
+                                .partial_segments
+                                .keys()
+                                .copied()
+                                .find(|start| seq_le(*start, self.recv_next))
+                            {
+                                let data = self.partial_segments.remove(&start).unwrap();
+                                let offset = self.recv_next.wrapping_sub(start) as usize;
+                                if offset < data.len() {
+                                    self.recv_window.extend(&data[offset..]);
+                                    self.recv_next =
+                                        self.recv_next.wrapping_add((data.len() - offset) as u32);
+                                }
+                            }
 
-                            while let Some(pkt) = self.partial_segments.get(&self.recv_next) {
-                                self.recv_window.extend_from_slice(&pkt);
-                                self.recv_next = self.recv_next.wrapping_add(pkt.len() as u32);
+                            // only report what is still buffered
+                            self.sack_blocks.retain_mut(|(start, end)| {
+                                if seq_le(*end, self.recv_next) {
+                                    return false;
+                                }
+                                if seq_lt(*start, self.recv_next) {
+                                    *start = self.recv_next;
+                                }
+                                true
+                            });
+                            // nobody will read it, but the peer mustn't stall
+                            if self.read_shutdown {
+                                self.recv_window.clear();
                             }
-                            self.partial_segments.retain(|k, _| *k > self.recv_next);
+                            self.update_recv_window();
                         } else {
                             debug!("received out-of-order segment");
 
+                            let end = seq.wrapping_add(payload.len() as u32);
+                            if self.partial_segments.iter().any(|(start, data)| {
+                                seq_le(*start, seq)
+                                    && seq_le(end, start.wrapping_add(data.len() as u32))
+                            }) {
+                                self.dsack_block = Some((seq, end));
+                            } else {
+                                self.partial_segments.insert(seq, payload.to_vec());
+                            }
+
                             // out-of-order segment, send an ACK for our current state (RFC5581)
-                            self.partial_segments
-                                .insert(pkt.sequence_number(), pkt.payload().to_vec());
+                            self.update_sack_blocks(seq);
                         }
 
-                        // TODO delayed ACK
-                        let mut header = self.header.clone();
-                        header.sequence_number = self.send_next;
-                        header.acknowledgment_number = self.recv_next;
-                        header.ack = true;
-                        self.transmit_payload(header, &[]).unwrap();
+                        if ack_now || self.dsack_block.is_some() {
+                            self.transmit_ack();
+                        } else {
+                            self.delay_ack();
+                        }
                     }
                 }
 
-                if pkt.fin() && pkt.sequence_number() == self.recv_next {
+                // the FIN follows the segment's data, and only counts once
+                // everything before it arrived
+                let fin = seq.wrapping_add(len);
+                if pkt.fin()
+                    && fin == self.recv_next
+                    && matches!(
+                        self.state,
+                        TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2
+                    )
+                {
                     debug!("received FIN, ACKing");
 
-                    // TODO if remote FIN is re-transmitted, this will never run?
-                    self.recv_next += 1;
-                    let mut header = self.header.clone();
-                    header.sequence_number = self.send_next;
-                    header.acknowledgment_number = self.recv_next;
-                    header.ack = true;
-                    self.transmit_payload(header, &[]).unwrap();
+                    self.recv_next = self.recv_next.wrapping_add(1);
+                    self.update_recv_window();
+                    self.transmit_ack();
 
                     match self.state {
                         TcpState::Established => self.set_state(TcpState::CloseWait),
-                        TcpState::FinWait1 => {
-                            if fin_acked {
-                                self.set_state(TcpState::TimeWait)
-                            }
-                        }
+                        TcpState::FinWait1 if fin_acked => self.set_state(TcpState::TimeWait),
+                        // simultaneous close, both FINs crossed
+                        TcpState::FinWait1 => self.set_state(TcpState::Closing),
                         TcpState::FinWait2 => self.set_state(TcpState::TimeWait),
-                        TcpState::TimeWait => self.set_state(TcpState::TimeWait),
                         _ => {}
                     }
                 }
@@ -535,8 +2036,9 @@ impl TcpSocket {
                     header.rst = true;
                     if !pkt.ack() {
                         header.sequence_number = 0;
-                        header.acknowledgment_number =
-                            pkt.sequence_number() + pkt.payload().len() as u32;
+                        header.acknowledgment_number = pkt
+                            .sequence_number()
+                            .wrapping_add(pkt.payload().len() as u32);
                         header.ack = true;
                     } else {
                         header.sequence_number = pkt.acknowledgment_number();
@@ -552,119 +2054,911 @@ impl TcpSocket {
 
     /// returns the number of bytes read and whether there might be more bytes in the future
     pub fn read(&mut self, buf: &mut [u8]) -> std::io::Result<(usize, bool)> {
-        Ok(if self.recv_window.is_empty() {
-            (
-                0,
-                if let TcpState::Established
-                | TcpState::FinWait1
-                | TcpState::FinWait2
-                | TcpState::CloseWait = self.state
-                {
-                    true
-                } else {
-                    false
-                },
-            )
-        } else {
-            let drained = self
-                .recv_window
-                .drain(0..buf.len().min(self.recv_window.len()));
-            buf[0..drained.len()].copy_from_slice(drained.as_slice());
-            (drained.len(), true)
-        })
-    }
-
-    pub fn write(&mut self, payload: &[u8]) -> std::io::Result<usize> {
-        match &self.state {
-            TcpState::Established => {}
-            state => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::NotConnected,
-                    format!("can't write in state {state:?}"),
-                ))
+        // the peer won't send more data after its FIN (CLOSE-WAIT onwards)
+        let can_receive = !self.read_shutdown
+            && matches!(
+                self.state,
+                TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2
+            );
+
+        if self.recv_window.is_empty() {
+            if let Some(kind) = self.error {
+                return Err(kind.into());
             }
+
+            return Ok((0, can_receive));
         }
 
-        // window size = 2
-        // SND.UNA = 2
-        // SND.NXT = 4
-        //  1    2    3    4
-        // ----|----|----|----|
-        let len = self.send_window.len();
-        let begin = self.send_unack as usize % len;
-        let end = self.send_next as usize % len;
+        // the buffered data might wrap around the end of the deque
+        let size = buf.len().min(self.recv_window.len());
+        let (front, back) = self.recv_window.as_slices();
+        let head = size.min(front.len());
+        buf[0..head].copy_from_slice(&front[0..head]);
+        buf[head..size].copy_from_slice(&back[0..size - head]);
+        self.recv_window.drain(0..size);
+
+        // let the peer know if the window opened up after being mostly full
+        let recv_wnd = self.recv_wnd();
+        if self.update_recv_window() && can_receive && recv_wnd < RECV_BUFFER_SIZE as u32 / 2 {
+            debug!(
+                old = recv_wnd,
+                new = self.recv_wnd(),
+                "sending window update"
+            );
+            self.transmit_ack();
+        }
 
-        let available_capacity = if begin <= end {
-            (len - (end - begin)).min(payload.len())
+        Ok((size, true))
+    }
+
+    /// RFC 9293 3.8.6.3, every second segment is acknowledged right away, the
+    /// ACK for a single one waits for `ack_delay` in case data can carry it
+    fn delay_ack(&mut self) {
+        self.delayed_segments += 1;
+
+        if self.ack_delay.is_zero() || self.delayed_segments >= 2 {
+            self.transmit_ack();
         } else {
-            begin - end
-        };
+            self.delayed_ack.get_or_insert_with(std::time::Instant::now);
+        }
+    }
 
-        if available_capacity > 0 {
-            for idx in 0..available_capacity {
-                self.send_window[(end + idx) % len] = payload[idx];
-            }
+    /// generates a delivery rate sample from the most recently sent segment
+    /// that `ack` acknowledges (draft-cheng-iccrg-delivery-rate-estimation)
+    fn on_delivery(&mut self, ack: u32) {
+        let now = std::time::Instant::now();
+        self.delivered += ack.wrapping_sub(self.send_unack) as u64;
+        self.delivered_time = now;
 
-            self.timers
-                .insert(self.send_next, (false, std::time::Instant::now()));
+        let Some(segment) = self
+            .timers
+            .iter()
+            .filter(|(seq, _)| seq_le(self.send_unack, **seq) && seq_lt(**seq, ack))
+            .map(|(_, segment)| segment)
+            .max_by_key(|segment| (segment.delivered, segment.sent))
+        else {
+            return;
+        };
 
-            self.header.sequence_number = self.send_next;
-            let mut header = self.header.clone();
-            header.psh = true;
-            self.send_next = self.send_next.wrapping_add(available_capacity as u32);
+        let send_elapsed = segment.sent.duration_since(segment.first_sent_time);
+        let ack_elapsed = now.duration_since(segment.delivered_time);
+        let sample = RateSample {
+            delivered: self.delivered - segment.delivered,
+            // ACKs can be compressed or stretched, the longer phase bounds the rate
+            interval: send_elapsed.max(ack_elapsed),
+            prior_delivered: segment.delivered,
+            total_delivered: self.delivered,
+            app_limited: segment.app_limited,
+            in_flight: self.send_max.wrapping_sub(ack),
+        };
+        self.first_sent_time = segment.sent;
 
-            self.transmit_payload(header, &payload[0..available_capacity])
-                .unwrap();
+        if self.app_limited_until != 0 && self.delivered > self.app_limited_until {
+            self.app_limited_until = 0;
         }
 
-        Ok(available_capacity)
+        if !sample.interval.is_zero() {
+            self.congestion.on_rate_sample(&sample);
+        }
     }
 
-    pub fn reset(&mut self) {
-        let span = self.get_span(None);
-        let _enter = span.enter();
+    /// RFC 5681 3.2, RFC 6582 3.2
+    fn on_duplicate_ack(&mut self) {
+        self.dup_acks += 1;
+        debug!(dup_acks = self.dup_acks, "received duplicate ACK");
 
-        match self.state {
-            TcpState::Closed => {}
-            TcpState::Closing | TcpState::LastAck | TcpState::TimeWait => {
-                self.set_state(TcpState::Closed)
-            }
-            _ => {
-                let mut header = self.header.clone();
-                header.rst = true;
-                header.sequence_number = self.send_next;
-                self.transmit_payload(header, &[]).unwrap();
-                self.set_state(TcpState::Closed);
+        if self.recovery_cwnd.is_some() {
+            if self.sack_permitted {
+                self.retransmit_lost();
+            } else if let Some(cwnd) = self.recovery_cwnd.as_mut() {
+                *cwnd = cwnd.saturating_add(self.send_mss as u32);
             }
+        } else if (self.dup_acks == DUP_ACK_THRESHOLD
+            || (self.sack_permitted
+                && self
+                    .scoreboard
+                    .is_lost(self.send_unack, self.send_mss as u32)))
+            && seq_le(self.recover, self.send_unack)
+        {
+            self.enter_recovery();
         }
     }
 
-    pub fn close(&mut self) {
-        let span = self.get_span(None);
-        let _enter = span.enter();
-
-        match self.state {
-            TcpState::Closed => {}
-            TcpState::SynSent => {
-                // TODO delete TCB
-            }
-            TcpState::Established => {
-                // TODO send FIN
-                self.set_state(TcpState::FinWait1);
-            }
-            TcpState::CloseWait => {
-                // TODO send FIN
-                self.set_state(TcpState::LastAck);
-            }
-            _ => {}
+    fn enter_recovery(&mut self) {
+        self.congestion
+            .on_loss(self.send_max.wrapping_sub(self.send_unack));
+        self.undo_marker = Some(self.send_unack);
+        self.undo_retrans = 0;
+        let cwnd = if self.sack_permitted {
+            // SACK recovery limits the data in flight through the pipe estimate
+            self.congestion.cwnd()
+        } else {
+            // the three duplicate ACKs mean as many segments left the network
+            self.congestion.cwnd() + DUP_ACK_THRESHOLD * self.send_mss as u32
+        };
+        info!(
+            cwnd,
+            ssthresh = self.congestion.ssthresh(),
+            sack = self.sack_permitted,
+            "fast retransmit, entering fast recovery"
+        );
+
+        self.recover = self.send_max;
+        self.recovery_cwnd = Some(cwnd);
+        self.retransmit_first_segment();
+
+        if self.sack_permitted {
+            self.high_rxt = self.send_unack.wrapping_add(
+                self.send_max
+                    .wrapping_sub(self.send_unack)
+                    .min(self.effective_mss()),
+            );
+            self.retransmit_lost();
         }
     }
 
-    fn transmit_payload(
-        &self,
-        header: etherparse::TcpHeader,
-        payload: &[u8],
-    ) -> Result<(), mpsc::SendError<Vec<u8>>> {
+    /// RFC 6675 5, retransmits the holes deemed lost while the pipe has room
+    fn retransmit_lost(&mut self) {
+        let Some(cwnd) = self.recovery_cwnd else {
+            return;
+        };
+
+        loop {
+            let mss = self.effective_mss();
+            if cwnd < self.pipe() + mss {
+                return;
+            }
+
+            let from = if seq_lt(self.high_rxt, self.send_unack) {
+                self.send_unack
+            } else {
+                self.high_rxt
+            };
+            let Some((seq, end)) =
+                self.scoreboard
+                    .next_lost(from, self.send_unack, self.send_mss as u32)
+            else {
+                return;
+            };
+
+            let end = if seq_lt(seq.wrapping_add(mss), end) {
+                seq.wrapping_add(mss)
+            } else {
+                end
+            };
+            debug!(seq, end, "retransmitting SACK hole");
+            self.transmit_segments(seq, end);
+            self.high_rxt = end;
+        }
+    }
+
+    /// bytes estimated to be in the network during SACK recovery
+    fn pipe(&self) -> u32 {
+        self.scoreboard.pipe(
+            self.send_unack,
+            self.send_max,
+            self.high_rxt,
+            self.send_mss as u32,
+        )
+    }
+
+    /// returns whether the SACK blocks covered new data
+    fn on_sack_options(&mut self, pkt: &etherparse::TcpSlice) -> bool {
+        let mut newly_sacked = false;
+
+        for option in tcp_options(pkt) {
+            if let Ok(etherparse::TcpOptionElement::SelectiveAcknowledgement(first, rest)) = option
+            {
+                // RFC 2883 4, a first block below the cumulative ACK or inside
+                // the second block reports duplicate data
+                let (start, end) = first;
+                if seq_le(end, pkt.acknowledgment_number())
+                    || rest[0].is_some_and(|(second_start, second_end)| {
+                        seq_le(second_start, start) && seq_le(end, second_end)
+                    })
+                {
+                    self.on_dsack(start, end);
+                }
+
+                for (start, end) in std::iter::once(first).chain(rest.into_iter().flatten()) {
+                    newly_sacked |=
+                        self.scoreboard
+                            .insert(start, end, self.send_unack, self.send_max);
+                }
+            }
+        }
+
+        newly_sacked
+    }
+
+    /// RFC 3708, the peer received `[start, end)` twice, so a retransmission
+    /// was needless; once all retransmissions since the last window reduction
+    /// turn out to be needless the reduction is undone
+    fn on_dsack(&mut self, start: u32, end: u32) {
+        self.spurious_retransmits += 1;
+        debug!(start, end, "peer reported duplicate data (D-SACK)");
+
+        let Some(undo_marker) = self.undo_marker else {
+            return;
+        };
+
+        // only retransmissions of the data outstanding at the reduction count
+        if self.undo_retrans == 0 || seq_lt(start, undo_marker) || seq_lt(self.recover, end) {
+            return;
+        }
+
+        self.undo_retrans = self.undo_retrans.saturating_sub(end.wrapping_sub(start));
+        if self.undo_retrans > 0 {
+            return;
+        }
+
+        self.congestion.undo();
+        self.undo_marker = None;
+        info!(
+            cwnd = self.congestion.cwnd(),
+            ssthresh = self.congestion.ssthresh(),
+            "retransmissions were spurious, undoing the window reduction"
+        );
+
+        // recovery goes on with the restored window in case there are holes left
+        if let Some(cwnd) = self.recovery_cwnd.as_mut() {
+            *cwnd = self.congestion.cwnd();
+        }
+    }
+
+    /// `acked` bytes of new data were acknowledged and SND.UNA advanced
+    fn on_new_ack(&mut self, acked: u32) {
+        let Some(cwnd) = self.recovery_cwnd.as_mut() else {
+            self.congestion.on_ack(acked);
+            return;
+        };
+
+        if seq_le(self.recover, self.send_unack) {
+            // continue with the window the congestion controller settled on
+            info!(cwnd = self.congestion.cwnd(), "leaving fast recovery");
+            self.recovery_cwnd = None;
+        } else if self.sack_permitted {
+            self.retransmit_lost();
+        } else {
+            // partial ACK, the next hole is also lost, deflate the window by
+            // the data that left the network
+            *cwnd = cwnd.saturating_sub(acked);
+            if acked >= self.send_mss as u32 {
+                *cwnd += self.send_mss as u32;
+            }
+
+            debug!(acked, "partial ACK, retransmitting next segment");
+            self.retransmit_first_segment();
+        }
+    }
+
+    /// reports the out-of-order data around `seq` first, followed by the
+    /// previously reported blocks it doesn't cover (RFC 2018 4)
+    fn update_sack_blocks(&mut self, seq: u32) {
+        // in sequence order from RCV.NXT, which the map's isn't across a wrap
+        let mut segments: Vec<_> = self.partial_segments.iter().collect();
+        segments.sort_by_key(|(start, _)| start.wrapping_sub(self.recv_next));
+
+        let mut blocks: Vec<(u32, u32)> = Vec::new();
+        for (start, payload) in segments {
+            let end = start.wrapping_add(payload.len() as u32);
+            match blocks.last_mut() {
+                Some((_, last_end)) if seq_le(*start, *last_end) => {
+                    if seq_lt(*last_end, end) {
+                        *last_end = end;
+                    }
+                }
+                _ => blocks.push((*start, end)),
+            }
+        }
+
+        let Some(block) = blocks
+            .into_iter()
+            .find(|(start, end)| seq_le(*start, seq) && seq_lt(seq, *end))
+        else {
+            return;
+        };
+
+        self.sack_blocks
+            .retain(|(start, end)| seq_lt(*end, block.0) || seq_lt(block.1, *start));
+        self.sack_blocks.insert(0, block);
+        self.sack_blocks.truncate(MAX_SACK_BLOCKS);
+    }
+
+    /// options carried by segments after the handshake
+    fn segment_options(&self) -> etherparse::TcpOptions {
+        // RFC 2883 4, the D-SACK block goes first, followed by the SACK block
+        // containing it if there is one
+        let mut sack_blocks: Vec<(u32, u32)> = Vec::with_capacity(MAX_SACK_BLOCKS);
+        if let Some((start, end)) = self.dsack_block {
+            sack_blocks.push((start, end));
+            sack_blocks.extend(
+                self.sack_blocks
+                    .iter()
+                    .find(|block| seq_le(block.0, start) && seq_le(end, block.1)),
+            );
+        }
+        let max_sack_blocks = if self.ts_enabled {
+            MAX_SACK_BLOCKS_WITH_TIMESTAMPS
+        } else {
+            MAX_SACK_BLOCKS
+        };
+        for block in &self.sack_blocks {
+            if sack_blocks.len() < max_sack_blocks
+                && !sack_blocks.iter().skip(1).any(|b| b == block)
+            {
+                sack_blocks.push(*block);
+            }
+        }
+
+        let mut options = Vec::new();
+
+        if self.ts_enabled {
+            options.extend([
+                etherparse::TcpOptionElement::Noop,
+                etherparse::TcpOptionElement::Noop,
+                etherparse::TcpOptionElement::Timestamp(self.ts_now(), self.ts_recent),
+            ]);
+        }
+
+        if let Some((first, rest)) = sack_blocks.split_first().filter(|_| self.sack_permitted) {
+            let mut blocks = [None; MAX_SACK_BLOCKS - 1];
+            for (slot, block) in blocks.iter_mut().zip(rest) {
+                *slot = Some(*block);
+            }
+
+            options.extend([
+                etherparse::TcpOptionElement::Noop,
+                etherparse::TcpOptionElement::Noop,
+                etherparse::TcpOptionElement::SelectiveAcknowledgement(*first, blocks),
+            ]);
+        }
+
+        let options = etherparse::TcpOptions::try_from_elements(&options).unwrap();
+
+        // RFC 5482 3, a changed user timeout is sent with the next segment
+        // that has room for it
+        match self.user_timeout.filter(|_| self.user_timeout_pending) {
+            Some(timeout) => with_user_timeout_option(&options, timeout).unwrap_or(options),
+            None => options,
+        }
+    }
+
+    /// TSval for segments sent now, in milliseconds
+    fn ts_now(&self) -> u32 {
+        self.ts_offset
+            .wrapping_add(self.ts_clock.elapsed().as_millis() as u32)
+    }
+
+    /// largest payload that fits in a segment next to the current options (RFC 6691)
+    fn effective_mss(&self) -> u32 {
+        (self.send_mss as u32)
+            .saturating_sub(self.segment_options().len() as u32)
+            .max(1)
+    }
+
+    /// window advertised by the segment, the window of SYN segments is never
+    /// scaled (RFC 7323 2.2)
+    fn peer_window(&self, pkt: &etherparse::TcpSlice) -> u32 {
+        if pkt.syn() {
+            pkt.window_size() as u32
+        } else {
+            (pkt.window_size() as u32) << self.send_wscale
+        }
+    }
+
+    /// window we last advertised, as the peer sees it
+    fn recv_wnd(&self) -> u32 {
+        (self.header.window_size as u32) << self.recv_wscale
+    }
+
+    fn set_send_window(&mut self, pkt: &etherparse::TcpSlice) {
+        self.send_wnd = self.peer_window(pkt);
+        self.send_wl1 = pkt.sequence_number();
+        self.send_wl2 = pkt.acknowledgment_number();
+        self.max_send_wnd = self.max_send_wnd.max(self.send_wnd);
+    }
+
+    /// receiver side SWS avoidance (RFC 1122 4.2.3.3), the right edge of the
+    /// advertised window only moves once it can advance by a significant amount,
+    /// returns whether it moved
+    fn update_recv_window(&mut self) -> bool {
+        let mss = self.local_mss as u32;
+        let available = RECV_BUFFER_SIZE.saturating_sub(self.recv_window.len()) as u32;
+        // offer whole segments so the peer isn't tempted to send partial ones
+        let available = available - available % mss;
+        let recv_wnd = if seq_lt(self.recv_next, self.recv_edge) {
+            self.recv_edge.wrapping_sub(self.recv_next)
+        } else {
+            0
+        };
+        let threshold = (RECV_BUFFER_SIZE as u32 / 2).min(mss);
+
+        let moved = available >= recv_wnd + threshold;
+        if moved {
+            self.recv_edge = self.recv_next.wrapping_add(available);
+        }
+
+        let recv_wnd = if seq_lt(self.recv_next, self.recv_edge) {
+            self.recv_edge.wrapping_sub(self.recv_next)
+        } else {
+            0
+        };
+        // round up rather than retract the edge by less than the scale
+        // granularity, SYN segments can't be scaled (RFC 7323 2.2)
+        self.header.window_size = if self.header.syn {
+            recv_wnd.min(0xFFFF) as u16
+        } else {
+            recv_wnd.div_ceil(1 << self.recv_wscale).min(0xFFFF) as u16
+        };
+
+        moved
+    }
+
+    pub fn write(&mut self, payload: &[u8]) -> std::io::Result<usize> {
+        if let Some(kind) = self.error {
+            return Err(kind.into());
+        }
+
+        if self.fin_seq.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "connection was shut down for writing",
+            ));
+        }
+
+        // RFC 9293 3.10.2, a half-closed connection can still send
+        match &self.state {
+            TcpState::Established | TcpState::CloseWait => {}
+            state => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotConnected,
+                    format!("can't write in state {state:?}"),
+                ))
+            }
+        }
+
+        // buffer size = 4
+        // SND.UNA = 1
+        // SND.NXT = 2
+        // queued = 3
+        //  1    2    3    4
+        // ----|----|----|----|
+        let len = self.send_buffer.len();
+        let used = self.send_queued.wrapping_sub(self.send_unack) as usize;
+        let end = self.send_queued as usize % len;
+
+        let available_capacity = (len - used).min(payload.len());
+
+        if available_capacity > 0 {
+            for (idx, byte) in payload[0..available_capacity].iter().enumerate() {
+                self.send_buffer[(end + idx) % len] = *byte;
+            }
+
+            self.send_queued = self.send_queued.wrapping_add(available_capacity as u32);
+            self.transmit_queued(false);
+        }
+
+        Ok(available_capacity)
+    }
+
+    /// switches to another congestion control algorithm, which starts over
+    /// from its initial window
+    pub fn set_congestion_control(&mut self, algorithm: CongestionAlgorithm) {
+        self.congestion_algorithm = algorithm;
+        self.congestion = algorithm.build(self.send_mss);
+        self.recovery_cwnd = None;
+    }
+
+    pub fn set_keepalive(&mut self, keepalive: Option<Keepalive>) {
+        self.keepalive = keepalive;
+        self.keepalive_probes = 0;
+    }
+
+    pub fn set_user_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        self.user_timeout = timeout;
+        self.user_timeout_pending = self.advertise_user_timeout && timeout.is_some();
+    }
+
+    pub fn set_advertise_user_timeout(&mut self, advertise: bool) {
+        self.advertise_user_timeout = advertise;
+        self.user_timeout_pending = advertise && self.user_timeout.is_some();
+    }
+
+    pub fn set_ack_delay(&mut self, delay: std::time::Duration) {
+        self.ack_delay = delay;
+
+        if delay.is_zero() && self.delayed_ack.is_some() {
+            self.transmit_ack();
+        }
+    }
+
+    pub fn set_nodelay(&mut self, nodelay: bool) {
+        self.nodelay = nodelay;
+
+        if nodelay {
+            self.transmit_queued(false);
+        }
+    }
+
+    /// transmits the data written by the user that hasn't been sent yet and
+    /// fits in both the peer's window and the congestion window, `force`
+    /// overrides SWS avoidance
+    fn transmit_queued(&mut self, force: bool) {
+        loop {
+            // don't resend data the peer SACKed after going back to SND.UNA
+            if let Some((_, end)) = self.scoreboard.range_at(self.send_next) {
+                self.send_next = end;
+                continue;
+            }
+
+            // SND.NXT is past the queued data once the FIN was sent
+            let queued = if seq_lt(self.send_next, self.send_queued) {
+                self.send_queued.wrapping_sub(self.send_next)
+            } else {
+                0
+            };
+            // limited transmit (RFC 3042), the first two duplicate ACKs can
+            // each release a new segment
+            let cwnd = self.recovery_cwnd.unwrap_or_else(|| {
+                self.congestion.cwnd() + self.dup_acks.min(2) * self.send_mss as u32
+            });
+            // the peer might have shrunk its window below SND.NXT
+            let usable_until = |edge: u32| {
+                if seq_lt(self.send_next, edge) {
+                    edge.wrapping_sub(self.send_next)
+                } else {
+                    0
+                }
+            };
+            let usable = usable_until(self.send_unack.wrapping_add(self.send_wnd)).min(
+                if self.recovery_cwnd.is_some() && self.sack_permitted {
+                    // RFC 6675 5, SACK recovery bounds the data in flight by the pipe estimate
+                    cwnd.saturating_sub(self.pipe())
+                } else {
+                    usable_until(self.send_unack.wrapping_add(cwnd))
+                },
+            );
+
+            let mss = self.effective_mss();
+            let mut size = queued.min(usable).min(mss);
+            if size == 0 {
+                // everything written before the shutdown was sent, the FIN
+                // follows regardless of the window
+                if queued == 0 && self.fin_seq == Some(self.send_next) {
+                    self.transmit_fin();
+                    return;
+                }
+
+                // the user didn't write enough to fill the window, rate samples
+                // until this data is acknowledged don't reflect the path
+                let in_flight = self.send_max.wrapping_sub(self.send_unack);
+                if queued == 0 && in_flight < cwnd {
+                    self.app_limited_until = (self.delivered + in_flight as u64).max(1);
+                }
+
+                // nothing will trigger a window update from the peer, start probing
+                if queued > 0 && self.send_wnd == 0 && self.send_next == self.send_unack {
+                    self.persist_instant
+                        .get_or_insert_with(std::time::Instant::now);
+                }
+
+                return;
+            }
+
+            let retransmission = seq_lt(self.send_next, self.send_max);
+            if let Some(sacked) = self.scoreboard.next_sacked(self.send_next) {
+                size = size.min(sacked.wrapping_sub(self.send_next));
+            }
+
+            // holding back data that was already sent only delays recovery,
+            // and nothing will be written after a shutdown to fill the segment
+            if size < mss && !force && !retransmission && self.fin_seq.is_none() {
+                // Nagle's algorithm (RFC 896), partial segments are held back
+                // while there is unacknowledged data
+                if !self.nodelay && self.send_next != self.send_unack {
+                    debug!(size, "holding back partial segment (Nagle)");
+                    return;
+                }
+
+                // sender side SWS avoidance (RFC 1122 4.2.3.4), a partial segment
+                // must carry all queued data or fill half of the peer's largest window
+                if size < queued && size < self.max_send_wnd / 2 {
+                    debug!(size, usable, "holding back partial segment (SWS)");
+                    self.sws_timer.get_or_insert_with(std::time::Instant::now);
+                    return;
+                }
+            }
+
+            if !self.take_pacing_budget(size) {
+                return;
+            }
+
+            self.sws_timer = None;
+            self.header.sequence_number = self.send_next;
+            let seq = self.send_next;
+            self.send_next = self.send_next.wrapping_add(size);
+
+            self.transmit_segments(seq, self.send_next);
+        }
+    }
+
+    /// returns whether `size` bytes can be sent without exceeding the pacing
+    /// rate of the congestion controller, and uses up that budget if so
+    fn take_pacing_budget(&mut self, size: u32) -> bool {
+        let Some(rate) = self.congestion.pacing_rate() else {
+            return true;
+        };
+
+        let now = std::time::Instant::now();
+        let elapsed = now.duration_since(self.pacing_instant).as_secs_f64();
+        let max_budget = (rate * PACING_QUANTUM.as_secs_f64()).max(2.0 * self.send_mss as f64);
+        self.pacing_budget = (self.pacing_budget + elapsed * rate).min(max_budget);
+        self.pacing_instant = now;
+
+        if self.pacing_budget < size as f64 {
+            return false;
+        }
+
+        self.pacing_budget -= size as f64;
+        true
+    }
+
+    pub fn reset(&mut self) {
+        let span = self.get_span(None);
+        let _enter = span.enter();
+
+        match self.state {
+            TcpState::Closed => {}
+            TcpState::Closing | TcpState::LastAck | TcpState::TimeWait => {
+                self.set_state(TcpState::Closed)
+            }
+            _ => {
+                let mut header = self.header.clone();
+                header.rst = true;
+                header.sequence_number = self.send_next;
+                self.transmit_payload(header, &[]).unwrap();
+                self.set_state(TcpState::Closed);
+            }
+        }
+    }
+
+    /// fails the connection with `kind`, resetting it if the peer knows about it
+    fn abort(&mut self, kind: std::io::ErrorKind) {
+        self.error = Some(kind);
+
+        match self.state {
+            // the peer never synchronized with us, there's nothing to reset
+            TcpState::SynSent | TcpState::SynReceived => self.set_state(TcpState::Closed),
+            _ => self.reset(),
+        }
+    }
+
+    /// sends a FIN after the data written so far, received data can still be
+    /// read until the peer's FIN arrives
+    pub fn close(&mut self) {
+        if !matches!(self.state, TcpState::Closed) {
+            self.shutdown(std::net::Shutdown::Write).unwrap();
+        }
+    }
+
+    /// stops reading, discarding received and future data, and/or writing,
+    /// sending a FIN after the data written so far
+    pub fn shutdown(&mut self, how: std::net::Shutdown) -> std::io::Result<()> {
+        let span = self.get_span(None);
+        let _enter = span.enter();
+
+        if let TcpState::Closed = self.state {
+            return Err(std::io::ErrorKind::NotConnected.into());
+        }
+
+        if let std::net::Shutdown::Read | std::net::Shutdown::Both = how {
+            self.read_shutdown = true;
+            self.recv_window.clear();
+            self.update_recv_window();
+            self.state_condvar.notify_all();
+        }
+
+        if let std::net::Shutdown::Write | std::net::Shutdown::Both = how {
+            match self.state {
+                TcpState::SynSent => {
+                    // RFC 9293 3.10.4, the peer doesn't know about us yet
+                    self.error = Some(std::io::ErrorKind::ConnectionAborted);
+                    self.set_state(TcpState::Closed);
+                }
+                // RFC 9293 3.10.4, the FIN waits for ESTABLISHED
+                TcpState::SynReceived => self.fin_deferred = true,
+                TcpState::Established => {
+                    self.set_state(TcpState::FinWait1);
+                    self.queue_fin();
+                }
+                TcpState::CloseWait => {
+                    self.set_state(TcpState::LastAck);
+                    self.queue_fin();
+                }
+                // the FIN was already queued
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// the FIN goes out with the sequence number after the last byte written
+    fn queue_fin(&mut self) {
+        self.fin_seq = Some(self.send_queued);
+        self.transmit_queued(false);
+    }
+
+    /// sends the FIN, tracked like data so it's retransmitted until acknowledged
+    fn transmit_fin(&mut self) {
+        let seq = self.send_next;
+        let retransmitted = seq_lt(seq, self.send_max);
+        if retransmitted {
+            self.retransmits += 1;
+        }
+
+        info!(retransmitted, "sending FIN");
+
+        let mut header = self.header.clone();
+        header.sequence_number = seq;
+        header.acknowledgment_number = self.recv_next;
+        header.fin = true;
+
+        self.track_segment(seq, retransmitted);
+        self.transmit_payload(header, &[]).unwrap();
+
+        self.send_next = seq.wrapping_add(1);
+        if seq_lt(self.send_max, self.send_next) {
+            self.send_max = self.send_next;
+        }
+    }
+
+    /// transmits `[seq, end)` from the send buffer, split into segments no
+    /// larger than the MSS, each with its own retransmission timer
+    fn transmit_segments(&mut self, mut seq: u32, end: u32) {
+        let len = self.send_buffer.len();
+
+        while seq != end {
+            let size = (end.wrapping_sub(seq) as usize).min(self.effective_mss() as usize);
+            let begin = seq as usize % len;
+
+            // the range might wrap around the end of the ring buffer
+            let mut payload = Vec::with_capacity(size);
+            let head = size.min(len - begin);
+            payload.extend_from_slice(&self.send_buffer[begin..begin + head]);
+            payload.extend_from_slice(&self.send_buffer[0..size - head]);
+
+            let mut header = self.header.clone();
+            header.sequence_number = seq;
+            header.acknowledgment_number = self.recv_next;
+            header.psh = seq.wrapping_add(size as u32) == end;
+
+            // anything below send_max was sent before and is useless for RTT measurements
+            let retransmitted = seq_lt(seq, self.send_max);
+            if retransmitted {
+                self.retransmits += 1;
+                if self.undo_marker.is_some() {
+                    self.undo_retrans = self.undo_retrans.saturating_add(size as u32);
+                }
+            }
+
+            self.track_segment(seq, retransmitted);
+            self.transmit_payload(header, &payload).unwrap();
+
+            seq = seq.wrapping_add(size as u32);
+            if seq_lt(self.send_max, seq) {
+                self.send_max = seq;
+            }
+        }
+    }
+
+    /// sequence number of the oldest outstanding segment, the map itself isn't
+    /// in sequence order once the sequence space wraps
+    fn first_timer(&self) -> Option<u32> {
+        self.timers
+            .keys()
+            .copied()
+            .min_by_key(|seq| seq.wrapping_sub(self.send_unack))
+    }
+
+    /// starts the retransmission timer for the segment at `seq` and records
+    /// the delivery rate estimation state at the time it was sent
+    fn track_segment(&mut self, seq: u32, retransmitted: bool) {
+        let now = std::time::Instant::now();
+
+        self.unacked_since.get_or_insert(now);
+
+        // nothing in flight, the sending interval of the next sample starts now
+        if self.send_max == self.send_unack {
+            self.first_sent_time = now;
+            self.delivered_time = now;
+        }
+
+        self.timers.insert(
+            seq,
+            SentSegment {
+                retransmitted,
+                sent: now,
+                delivered: self.delivered,
+                delivered_time: self.delivered_time,
+                first_sent_time: self.first_sent_time,
+                app_limited: self.app_limited_until != 0,
+            },
+        );
+    }
+
+    /// retransmits the segment starting at SND.UNA
+    fn retransmit_first_segment(&mut self) {
+        let size = self
+            .send_max
+            .wrapping_sub(self.send_unack)
+            .min(self.effective_mss());
+        self.transmit_segments(self.send_unack, self.send_unack.wrapping_add(size));
+    }
+
+    /// sends the next byte of queued data past the closed window, without
+    /// advancing SND.NXT
+    fn transmit_window_probe(&mut self) {
+        let mut header = self.header.clone();
+        header.sequence_number = self.send_next;
+        header.acknowledgment_number = self.recv_next;
+
+        let byte = self.send_buffer[self.send_next as usize % self.send_buffer.len()];
+        self.transmit_payload(header, &[byte]).unwrap();
+    }
+
+    /// RFC 1122 4.2.3.6, a segment below SND.NXT makes the peer reply with an ACK
+    fn transmit_keepalive(&mut self) {
+        let mut header = self.header.clone();
+        header.sequence_number = self.send_next.wrapping_sub(1);
+        header.acknowledgment_number = self.recv_next;
+        header.ack = true;
+        self.transmit_payload(header, &[]).unwrap();
+    }
+
+    /// RFC 5961, an ACK for suspicious segments that the real peer can act on,
+    /// within the limit shared by the device
+    fn transmit_challenge_ack(&mut self) {
+        if self.challenge_acks.allow() {
+            self.transmit_ack();
+        } else {
+            debug!("challenge ACK limit reached, not sending");
+        }
+    }
+
+    fn transmit_ack(&mut self) {
+        let mut header = self.header.clone();
+        header.sequence_number = self.send_next;
+        header.acknowledgment_number = self.recv_next;
+        header.ack = true;
+        self.transmit_payload(header, &[]).unwrap();
+    }
+
+    fn transmit_payload(
+        &mut self,
+        mut header: etherparse::TcpHeader,
+        payload: &[u8],
+    ) -> Result<(), mpsc::SendError<Vec<u8>>> {
+        // RFC 7323 3.2, once negotiated every segment but RSTs carries a
+        // timestamp, along with any SACK blocks
+        if !header.syn && !header.rst {
+            header.options = self.segment_options();
+            self.dsack_block = None;
+            self.user_timeout_pending &= !raw_options(header.options.as_slice())
+                .any(|(kind, _)| kind == USER_TIMEOUT_OPTION);
+        }
+
+        if header.ack {
+            self.last_ack_sent = header.acknowledgment_number;
+
+            // the ACK was sent or piggybacked on data, nothing is left to delay
+            if header.acknowledgment_number == self.recv_next {
+                self.delayed_ack = None;
+                self.delayed_segments = 0;
+            }
+        }
+
         let tcp = etherparse::PacketBuilder::ipv4(self.source_ip, self.destination_ip, 64)
             .tcp_header(header);
         let mut result = Vec::with_capacity(tcp.size(0));
@@ -672,3 +2966,553 @@ impl TcpSocket {
         self.tx.send(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use etherparse::{TcpHeader, TcpOptionElement};
+
+    /// initial sequence number of the peer
+    const PEER_ISS: u32 = 5000;
+
+    fn quad() -> Quad {
+        (
+            "10.0.0.1:9000".parse().unwrap(),
+            "10.0.0.2:40000".parse().unwrap(),
+        )
+    }
+
+    fn challenge_acks() -> Arc<ChallengeAckLimiter> {
+        Arc::new(ChallengeAckLimiter::new(DEFAULT_CHALLENGE_ACK_LIMIT))
+    }
+
+    fn socket(iss: u32) -> (TcpSocket, mpsc::Receiver<Vec<u8>>) {
+        let (tx, rx) = mpsc::channel();
+        let socket = TcpSocket::new(quad().0, quad().1, iss, tx, challenge_acks(), 1500);
+        (socket, rx)
+    }
+
+    /// a segment from the peer, acknowledging `ack` if set
+    fn segment(seq: u32, ack: Option<u32>) -> TcpHeader {
+        let mut header = TcpHeader::new(quad().1.port(), quad().0.port(), seq, 0xFFFF);
+        if let Some(ack) = ack {
+            header.ack = true;
+            header.acknowledgment_number = ack;
+        }
+        header
+    }
+
+    fn to_bytes(header: &TcpHeader, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = header.to_bytes().to_vec();
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    fn deliver(socket: &mut TcpSocket, header: &TcpHeader, payload: &[u8]) {
+        let bytes = to_bytes(header, payload);
+        socket.on_packet(etherparse::TcpSlice::from_slice(&bytes).unwrap());
+    }
+
+    /// segments sent since the last call, along with their payload
+    fn sent(rx: &mpsc::Receiver<Vec<u8>>) -> Vec<(TcpHeader, Vec<u8>)> {
+        rx.try_iter()
+            .map(|packet| {
+                let ip = etherparse::Ipv4HeaderSlice::from_slice(&packet).unwrap();
+                let (header, payload) = TcpHeader::from_slice(&packet[ip.slice().len()..]).unwrap();
+                (header, payload.to_vec())
+            })
+            .collect()
+    }
+
+    fn options(header: &TcpHeader) -> Vec<TcpOptionElement> {
+        header
+            .options_iterator()
+            .filter_map(Result::ok)
+            .filter(|option| !matches!(option, TcpOptionElement::Noop))
+            .collect()
+    }
+
+    /// SACK blocks of a segment, relative to the peer's ISS
+    fn sack_blocks(header: &TcpHeader) -> Vec<(u32, u32)> {
+        options(header)
+            .into_iter()
+            .flat_map(|option| match option {
+                TcpOptionElement::SelectiveAcknowledgement(first, rest) => std::iter::once(first)
+                    .chain(rest.into_iter().flatten())
+                    .collect(),
+                _ => Vec::new(),
+            })
+            .map(|(start, end)| (start - PEER_ISS, end - PEER_ISS))
+            .collect()
+    }
+
+    /// completes an active open with a SYN-ACK carrying `options`
+    fn established(iss: u32, options: &[TcpOptionElement]) -> (TcpSocket, mpsc::Receiver<Vec<u8>>) {
+        let (mut socket, rx) = socket(iss);
+        socket.connect();
+
+        let mut syn_ack = segment(PEER_ISS, Some(iss.wrapping_add(1)));
+        syn_ack.syn = true;
+        syn_ack.set_options(options).unwrap();
+        deliver(&mut socket, &syn_ack, &[]);
+        assert!(matches!(socket.state, TcpState::Established));
+
+        sent(&rx);
+        (socket, rx)
+    }
+
+    fn on_segment(
+        queue: &mut ListenQueue,
+        quad: Quad,
+        header: &TcpHeader,
+        tx: &mpsc::Sender<Vec<u8>>,
+    ) -> Option<Arc<Mutex<TcpSocket>>> {
+        let bytes = to_bytes(header, &[]);
+        queue.on_segment(
+            quad,
+            etherparse::TcpSlice::from_slice(&bytes).unwrap(),
+            tx.clone(),
+            challenge_acks(),
+            &|_: &Quad| 1000,
+            1500,
+        )
+    }
+
+    #[test]
+    fn passive_open_goes_through_the_listen_queue() {
+        let (tx, rx) = mpsc::channel();
+        let queue = ListenQueue::new();
+        let condvar = queue.state_condvar();
+        let listener = TcpListener::new(quad().0, Arc::new(Mutex::new(queue)), condvar);
+
+        let mut syn = segment(PEER_ISS, None);
+        syn.syn = true;
+        syn.set_options(&[TcpOptionElement::MaximumSegmentSize(1200)])
+            .unwrap();
+        let socket = on_segment(&mut listener.queue.lock().unwrap(), quad(), &syn, &tx).unwrap();
+
+        let [(syn_ack, _)] = sent(&rx).try_into().unwrap();
+        assert!(syn_ack.syn && syn_ack.ack);
+        assert_eq!(syn_ack.sequence_number, 1000);
+        assert_eq!(syn_ack.acknowledgment_number, PEER_ISS + 1);
+        assert_eq!(
+            options(&syn_ack),
+            [TcpOptionElement::MaximumSegmentSize(1460)]
+        );
+        assert!(matches!(
+            socket.lock().unwrap().state,
+            TcpState::SynReceived
+        ));
+
+        // still half-open
+        listener.queue.lock().unwrap().on_packet(quad(), &socket);
+        assert!(listener.queue.lock().unwrap().accept_queue.is_empty());
+
+        deliver(
+            &mut socket.lock().unwrap(),
+            &segment(PEER_ISS + 1, Some(1001)),
+            &[],
+        );
+        listener.queue.lock().unwrap().on_packet(quad(), &socket);
+        assert!(listener.queue.lock().unwrap().syn_queue.is_empty());
+
+        let (accepted, remote_addr) = listener.accept().unwrap();
+        assert_eq!(remote_addr, quad().1);
+        let accepted = accepted.socket.lock().unwrap();
+        assert!(matches!(accepted.state, TcpState::Established));
+        assert_eq!(accepted.send_mss, 1200);
+        assert_eq!(accepted.recv_next, PEER_ISS + 1);
+    }
+
+    #[test]
+    fn listen_queue_resets_stray_acks() {
+        let (tx, rx) = mpsc::channel();
+        let mut queue = ListenQueue::new();
+
+        assert!(on_segment(&mut queue, quad(), &segment(PEER_ISS, Some(1234)), &tx).is_none());
+        let mut syn_ack = segment(PEER_ISS, Some(5678));
+        syn_ack.syn = true;
+        assert!(on_segment(&mut queue, quad(), &syn_ack, &tx).is_none());
+
+        let rsts: Vec<_> = sent(&rx)
+            .into_iter()
+            .map(|(header, _)| {
+                assert!(header.rst && !header.ack && !header.syn);
+                header.sequence_number
+            })
+            .collect();
+        assert_eq!(rsts, [1234, 5678]);
+
+        // RSTs and segments without SYN or ACK are dropped
+        let mut rst = segment(PEER_ISS, None);
+        rst.rst = true;
+        assert!(on_segment(&mut queue, quad(), &rst, &tx).is_none());
+        assert!(on_segment(&mut queue, quad(), &segment(PEER_ISS, None), &tx).is_none());
+        assert!(sent(&rx).is_empty());
+    }
+
+    #[test]
+    fn peer_mss_is_clamped() {
+        let (socket, _rx) = established(1000, &[TcpOptionElement::MaximumSegmentSize(40)]);
+        assert_eq!(socket.send_mss, MIN_SEND_MSS);
+
+        let (socket, _rx) = established(1000, &[TcpOptionElement::MaximumSegmentSize(9000)]);
+        assert_eq!(socket.send_mss, 1460);
+
+        let (socket, _rx) = established(1000, &[]);
+        assert_eq!(socket.send_mss, DEFAULT_MSS);
+    }
+
+    #[test]
+    fn data_is_split_into_mss_sized_segments_across_the_ring_buffer() {
+        // the data starts 1500 bytes before the end of both the ring buffer
+        // and the sequence space
+        let iss = u32::MAX - 1500;
+        let (mut socket, rx) = established(iss, &[TcpOptionElement::MaximumSegmentSize(1000)]);
+
+        let data: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+        assert_eq!(socket.write(&data).unwrap(), 3000);
+
+        let segments = sent(&rx);
+        assert_eq!(segments.len(), 3);
+        for (i, (header, payload)) in segments.iter().enumerate() {
+            assert_eq!(
+                header.sequence_number,
+                iss.wrapping_add(1 + 1000 * i as u32)
+            );
+            assert_eq!(payload[..], data[1000 * i..1000 * (i + 1)]);
+        }
+    }
+
+    #[test]
+    fn segments_leave_room_for_options() {
+        let (mut socket, rx) = established(
+            1000,
+            &[
+                TcpOptionElement::MaximumSegmentSize(1000),
+                TcpOptionElement::Timestamp(1, 0),
+            ],
+        );
+
+        socket.write(&[0; 4000]).unwrap();
+
+        // the rest of the window is held back as a partial segment
+        let sizes: Vec<_> = sent(&rx)
+            .into_iter()
+            .map(|(_, payload)| payload.len())
+            .collect();
+        assert_eq!(sizes, [988, 988, 988, 988]);
+    }
+
+    #[test]
+    fn sack_blocks_report_the_most_recent_first() {
+        let (mut socket, rx) =
+            established(1000, &[TcpOptionElement::SelectiveAcknowledgementPermitted]);
+
+        let mut deliver_at = |offset: u32| {
+            deliver(
+                &mut socket,
+                &segment(PEER_ISS + offset, Some(1001)),
+                &[0; 100],
+            );
+            let [(ack, _)] = sent(&rx).try_into().unwrap();
+            (ack.acknowledgment_number - PEER_ISS, sack_blocks(&ack))
+        };
+
+        assert_eq!(deliver_at(101), (1, vec![(101, 201)]));
+        assert_eq!(deliver_at(301), (1, vec![(301, 401), (101, 201)]));
+        assert_eq!(
+            deliver_at(501),
+            (1, vec![(501, 601), (301, 401), (101, 201)])
+        );
+        // filling a gap merges the blocks around it
+        assert_eq!(deliver_at(201), (1, vec![(101, 401), (501, 601)]));
+        // only what is still out of order is reported
+        assert_eq!(deliver_at(1), (401, vec![(501, 601)]));
+    }
+
+    #[test]
+    fn duplicates_are_reported_with_a_dsack_block() {
+        let (mut socket, rx) =
+            established(1000, &[TcpOptionElement::SelectiveAcknowledgementPermitted]);
+        socket.set_ack_delay(std::time::Duration::ZERO);
+
+        let mut deliver_at = |offset: u32, len: usize| {
+            deliver(
+                &mut socket,
+                &segment(PEER_ISS + offset, Some(1001)),
+                &vec![0; len],
+            );
+            let [(ack, _)] = sent(&rx).try_into().unwrap();
+            (ack.acknowledgment_number - PEER_ISS, sack_blocks(&ack))
+        };
+
+        assert_eq!(deliver_at(1, 100), (101, vec![]));
+        // an old duplicate is reported below the ACK
+        assert_eq!(deliver_at(1, 100), (101, vec![(1, 101)]));
+
+        // a duplicate out-of-order segment is followed by the block containing it
+        assert_eq!(deliver_at(201, 100), (101, vec![(201, 301)]));
+        assert_eq!(deliver_at(201, 100), (101, vec![(201, 301), (201, 301)]));
+
+        // only the already received head of a segment is a duplicate
+        assert_eq!(deliver_at(51, 100), (151, vec![(51, 101), (201, 301)]));
+
+        // and it's reported just once
+        assert_eq!(deliver_at(151, 50), (301, vec![]));
+    }
+
+    #[test]
+    fn paws_rejects_segments_with_old_timestamps() {
+        let (mut socket, rx) = established(1000, &[TcpOptionElement::Timestamp(1000, 0)]);
+        socket.set_ack_delay(std::time::Duration::ZERO);
+
+        let mut deliver_with = |offset: u32, timestamp: Option<u32>| {
+            let mut header = segment(PEER_ISS + offset, Some(1001));
+            if let Some(ts_val) = timestamp {
+                header
+                    .set_options(&[TcpOptionElement::Timestamp(ts_val, 0)])
+                    .unwrap();
+            }
+            deliver(&mut socket, &header, &[0; 100]);
+            sent(&rx)
+                .into_iter()
+                .map(|(ack, _)| {
+                    let ts_ecr = options(&ack).into_iter().find_map(|option| match option {
+                        TcpOptionElement::Timestamp(_, ts_ecr) => Some(ts_ecr),
+                        _ => None,
+                    });
+                    (ack.acknowledgment_number - PEER_ISS, ts_ecr)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(deliver_with(1, Some(2000)), [(101, Some(2000))]);
+        // an old duplicate only gets a duplicate ACK
+        assert_eq!(deliver_with(101, Some(1500)), [(101, Some(2000))]);
+        // once negotiated, segments without a timestamp are dropped
+        assert!(deliver_with(101, None).is_empty());
+
+        assert_eq!(socket.recv_next, PEER_ISS + 101);
+    }
+
+    #[test]
+    fn shutdown_in_close_wait_sends_the_last_fin() {
+        let (mut socket, rx) = established(1000, &[]);
+
+        let mut fin = segment(PEER_ISS + 1, Some(1001));
+        fin.fin = true;
+        deliver(&mut socket, &fin, b"hello");
+        assert!(matches!(socket.state, TcpState::CloseWait));
+        let [(ack, _)] = sent(&rx).try_into().unwrap();
+        assert_eq!(ack.acknowledgment_number, PEER_ISS + 7);
+
+        let mut buf = [0; 16];
+        assert_eq!(socket.read(&mut buf).unwrap(), (5, true));
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(socket.read(&mut buf).unwrap(), (0, false));
+
+        socket.shutdown(std::net::Shutdown::Write).unwrap();
+        assert!(matches!(socket.state, TcpState::LastAck));
+        let [(fin, _)] = sent(&rx).try_into().unwrap();
+        assert!(fin.fin);
+        assert_eq!(fin.sequence_number, 1001);
+
+        deliver(&mut socket, &segment(PEER_ISS + 7, Some(1002)), &[]);
+        assert!(matches!(socket.state, TcpState::Closed));
+    }
+
+    #[test]
+    fn close_only_shuts_down_writing() {
+        let (mut socket, rx) = established(1000, &[]);
+
+        socket.close();
+        assert!(matches!(socket.state, TcpState::FinWait1));
+        let [(fin, _)] = sent(&rx).try_into().unwrap();
+        assert!(fin.fin);
+
+        deliver(&mut socket, &segment(PEER_ISS + 1, Some(1002)), b"hello");
+        assert!(matches!(socket.state, TcpState::FinWait2));
+
+        let mut buf = [0; 16];
+        assert_eq!(socket.read(&mut buf).unwrap(), (5, true));
+        assert_eq!(&buf[..5], b"hello");
+    }
+
+    #[test]
+    fn shutdown_in_syn_received_defers_the_fin() {
+        let (tx, rx) = mpsc::channel();
+        let mut queue = ListenQueue::new();
+
+        let mut syn = segment(PEER_ISS, None);
+        syn.syn = true;
+        let socket = on_segment(&mut queue, quad(), &syn, &tx).unwrap();
+        let mut socket = socket.lock().unwrap();
+        sent(&rx);
+
+        socket.shutdown(std::net::Shutdown::Write).unwrap();
+        assert!(matches!(socket.state, TcpState::SynReceived));
+        assert!(sent(&rx).is_empty());
+
+        deliver(&mut socket, &segment(PEER_ISS + 1, Some(1001)), &[]);
+        assert!(matches!(socket.state, TcpState::FinWait1));
+        let [(fin, _)] = sent(&rx).try_into().unwrap();
+        assert!(fin.fin);
+        assert_eq!(fin.sequence_number, 1001);
+    }
+
+    #[test]
+    fn simultaneous_open() {
+        let (mut socket, rx) = socket(1000);
+        socket.connect();
+        let [(syn, _)] = sent(&rx).try_into().unwrap();
+        assert!(syn.syn && !syn.ack);
+
+        // the SYNs crossed
+        let mut peer_syn = segment(PEER_ISS, None);
+        peer_syn.syn = true;
+        deliver(&mut socket, &peer_syn, &[]);
+        assert!(matches!(socket.state, TcpState::SynReceived));
+        let [(syn_ack, _)] = sent(&rx).try_into().unwrap();
+        assert!(syn_ack.syn && syn_ack.ack);
+        assert_eq!(syn_ack.sequence_number, 1000);
+        assert_eq!(syn_ack.acknowledgment_number, PEER_ISS + 1);
+
+        let mut peer_syn_ack = segment(PEER_ISS, Some(1001));
+        peer_syn_ack.syn = true;
+        deliver(&mut socket, &peer_syn_ack, &[]);
+        assert!(matches!(socket.state, TcpState::Established));
+        assert_eq!(socket.send_unack, 1001);
+    }
+
+    #[test]
+    fn simultaneous_close() {
+        let (mut socket, rx) = established(1000, &[]);
+
+        socket.close();
+        let [(fin, _)] = sent(&rx).try_into().unwrap();
+        assert!(fin.fin);
+
+        // the FINs crossed
+        let mut peer_fin = segment(PEER_ISS + 1, Some(1001));
+        peer_fin.fin = true;
+        deliver(&mut socket, &peer_fin, &[]);
+        assert!(matches!(socket.state, TcpState::Closing));
+        let [(ack, _)] = sent(&rx).try_into().unwrap();
+        assert_eq!(ack.acknowledgment_number, PEER_ISS + 2);
+
+        deliver(&mut socket, &segment(PEER_ISS + 2, Some(1002)), &[]);
+        assert!(matches!(socket.state, TcpState::TimeWait));
+    }
+
+    #[test]
+    fn suspicious_segments_get_a_challenge_ack() {
+        let (mut socket, rx) = established(1000, &[]);
+
+        let mut challenge = |header: &TcpHeader| {
+            deliver(&mut socket, header, &[]);
+            let [(ack, _)] = sent(&rx).try_into().unwrap();
+            assert!(ack.ack && !ack.syn && !ack.rst);
+            assert_eq!(ack.sequence_number, 1001);
+            assert_eq!(ack.acknowledgment_number, PEER_ISS + 1);
+        };
+
+        // a SYN in the window
+        let mut syn = segment(PEER_ISS + 10, None);
+        syn.syn = true;
+        challenge(&syn);
+
+        // a RST in the window, but not at RCV.NXT
+        let mut rst = segment(PEER_ISS + 10, None);
+        rst.rst = true;
+        challenge(&rst);
+
+        // an ACK for data never sent
+        challenge(&segment(PEER_ISS + 1, Some(2000)));
+
+        assert!(matches!(socket.state, TcpState::Established));
+
+        let mut rst = segment(PEER_ISS + 1, None);
+        rst.rst = true;
+        deliver(&mut socket, &rst, &[]);
+        assert!(matches!(socket.state, TcpState::Closed));
+        assert_eq!(socket.error, Some(std::io::ErrorKind::ConnectionReset));
+        assert!(sent(&rx).is_empty());
+    }
+
+    #[test]
+    fn syn_cookie_ack_creates_the_connection() {
+        let (tx, rx) = mpsc::channel();
+        let mut queue = ListenQueue::new();
+
+        // half-open connections from other ports fill the backlog
+        let mut syn = segment(PEER_ISS, None);
+        syn.syn = true;
+        let half_open: Vec<_> = (0..MAX_SYN_BACKLOG as u16)
+            .map(|i| {
+                let (local, remote) = quad();
+                let remote = SocketAddrV4::new(*remote.ip(), 50000 + i);
+                on_segment(&mut queue, (local, remote), &syn, &tx).unwrap()
+            })
+            .collect();
+        sent(&rx);
+
+        syn.set_options(&[
+            TcpOptionElement::MaximumSegmentSize(1460),
+            TcpOptionElement::SelectiveAcknowledgementPermitted,
+            TcpOptionElement::WindowScale(7),
+            TcpOptionElement::Timestamp(777, 0),
+        ])
+        .unwrap();
+        assert!(on_segment(&mut queue, quad(), &syn, &tx).is_none());
+        assert_eq!(queue.syn_queue.len(), half_open.len());
+
+        let [(syn_ack, _)] = sent(&rx).try_into().unwrap();
+        assert!(syn_ack.syn && syn_ack.ack);
+        assert_eq!(syn_ack.acknowledgment_number, PEER_ISS + 1);
+        let cookie = syn_ack.sequence_number;
+        let ts_val = options(&syn_ack)
+            .into_iter()
+            .find_map(|option| match option {
+                TcpOptionElement::Timestamp(ts_val, ts_ecr) => {
+                    assert_eq!(ts_ecr, 777);
+                    Some(ts_val)
+                }
+                _ => None,
+            })
+            .unwrap();
+
+        // a guessed cookie is refused
+        let forged = cookie.wrapping_add(1 << 20).wrapping_add(1);
+        assert!(on_segment(
+            &mut queue,
+            quad(),
+            &segment(PEER_ISS + 1, Some(forged)),
+            &tx
+        )
+        .is_none());
+        let [(rst, _)] = sent(&rx).try_into().unwrap();
+        assert!(rst.rst);
+        assert_eq!(rst.sequence_number, forged);
+
+        let mut ack = segment(PEER_ISS + 1, Some(cookie.wrapping_add(1)));
+        ack.set_options(&[TcpOptionElement::Timestamp(778, ts_val)])
+            .unwrap();
+        let socket = on_segment(&mut queue, quad(), &ack, &tx).unwrap();
+        assert!(sent(&rx).is_empty());
+
+        let (remote_addr, accepted) = queue.accept_queue.pop_front().unwrap();
+        assert_eq!(remote_addr, quad().1);
+        assert!(Arc::ptr_eq(&accepted, &socket));
+
+        let socket = socket.lock().unwrap();
+        assert!(matches!(socket.state, TcpState::Established));
+        assert_eq!(socket.send_unack, cookie.wrapping_add(1));
+        assert_eq!(socket.recv_next, PEER_ISS + 1);
+        assert_eq!(socket.send_mss, 1460);
+        assert!(socket.sack_permitted);
+        assert_eq!(socket.send_wscale, 7);
+        assert!(socket.ts_enabled);
+        assert_eq!(socket.ts_recent, 778);
+    }
+}
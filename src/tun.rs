@@ -1,4 +1,4 @@
-use crate::{tcp, util};
+use crate::{isn, tcp, util};
 use nix::{
     fcntl::OFlag,
     libc,
@@ -12,25 +12,43 @@ use std::{
     net::{Ipv4Addr, SocketAddrV4},
     os::fd::{AsRawFd, FromRawFd, OwnedFd},
     sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
 };
 use tracing::{error, info, warn};
 
-ioctl_write_int!(tunsetiff, b'T' as u8, 202 as u32);
+ioctl_write_int!(tunsetiff, b'T', 202);
 ioctl_write_ptr_bad!(siocsifaddr, libc::SIOCSIFADDR, libc::ifreq);
 ioctl_read_bad!(siocgifhwaddr, libc::SIOCGIFHWADDR, libc::ifreq);
+ioctl_read_bad!(siocgifmtu, libc::SIOCGIFMTU, libc::ifreq);
+
+/// how often the socket timers run
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
 
 pub struct TunDevice {
     pub devname: String,
     pub ip: [u8; 4],
     pub mac: [u8; 6],
+    pub mtu: u16,
     tap_fd: OwnedFd,
-    quad_to_socket: Mutex<HashMap<(SocketAddrV4, SocketAddrV4), Arc<Mutex<tcp::TcpSocket>>>>,
+    quad_to_socket: Mutex<HashMap<tcp::Quad, Arc<Mutex<tcp::TcpSocket>>>>,
+    listeners: Mutex<HashMap<u16, Arc<Mutex<tcp::ListenQueue>>>>,
     tx: mpsc::Sender<Vec<u8>>,
-    writer_jh: std::thread::JoinHandle<()>,
+    /// shared by all sockets, so the limit holds for the whole stack
+    challenge_acks: Arc<tcp::ChallengeAckLimiter>,
+    isn: Box<dyn isn::IsnGenerator>,
+    _writer_jh: std::thread::JoinHandle<()>,
 }
 
 impl TunDevice {
     pub fn new(devname: &str) -> Result<Self, std::io::Error> {
+        Self::with_isn_generator(devname, Box::new(isn::KeyedIsnGenerator::new()))
+    }
+
+    /// like `new`, but initial sequence numbers come from `isn`
+    pub fn with_isn_generator(
+        devname: &str,
+        isn: Box<dyn isn::IsnGenerator>,
+    ) -> Result<Self, std::io::Error> {
         let tap_fd = unsafe {
             OwnedFd::from_raw_fd(nix::fcntl::open(
                 "/dev/net/tun",
@@ -78,7 +96,7 @@ impl TunDevice {
         let (tx, rx): (mpsc::Sender<Vec<u8>>, mpsc::Receiver<Vec<u8>>) = mpsc::channel();
 
         let raw_fd = tap_fd.as_raw_fd();
-        let writer_jh = std::thread::spawn(move || loop {
+        let _writer_jh = std::thread::spawn(move || loop {
             nix::unistd::write(raw_fd, &rx.recv().unwrap()).unwrap();
         });
 
@@ -86,10 +104,16 @@ impl TunDevice {
             devname: String::from(devname),
             ip: [10, 0, 0, 1],
             mac: Self::get_mac_addr(devname)?,
+            mtu: Self::get_mtu(devname)?,
             quad_to_socket: Mutex::new(HashMap::new()),
+            listeners: Mutex::new(HashMap::new()),
             tap_fd,
             tx,
-            writer_jh,
+            challenge_acks: Arc::new(tcp::ChallengeAckLimiter::new(
+                tcp::DEFAULT_CHALLENGE_ACK_LIMIT,
+            )),
+            isn,
+            _writer_jh,
         })
     }
 
@@ -137,25 +161,50 @@ impl TunDevice {
         Ok(mac)
     }
 
+    fn get_mtu(devname: &str) -> Result<u16, std::io::Error> {
+        let sockfd = nix::sys::socket::socket(
+            AddressFamily::Inet,
+            SockType::Datagram,
+            SockFlag::empty(),
+            None,
+        )?;
+
+        let mut ifreq = util::create_ifreq(devname, 0);
+
+        unsafe {
+            siocgifmtu(sockfd.as_raw_fd(), &mut ifreq)?;
+            Ok(ifreq.ifr_ifru.ifru_mtu as u16)
+        }
+    }
+
     pub fn read_packets(&self) -> Result<(), std::io::Error> {
+        let mut last_tick = Instant::now();
+
         loop {
             let mut buf = vec![0_u8; 65536];
-            // TODO if there is a constant stream of data coming then this might
-            // not fire very frequently, add logic to compute duration from last tick
-            // and fire tick() accordingly
-            if nix::poll::poll(
+            let ready = nix::poll::poll(
                 &mut [nix::poll::PollFd::new(
                     &self.tap_fd,
                     nix::poll::PollFlags::POLLIN,
                 )],
-                10,
-            )? == 0
-            {
-                // remove sockets that have passed 2MSL after reacing TIME-WAIT state
-                self.quad_to_socket
-                    .lock()
-                    .unwrap()
-                    .retain(|_, socket| !socket.lock().unwrap().tick());
+                TICK_INTERVAL.as_millis() as i32,
+            )? > 0;
+
+            // a constant stream of packets mustn't hold the timers back
+            if last_tick.elapsed() >= TICK_INTERVAL {
+                last_tick = Instant::now();
+
+                // remove sockets that have passed 2MSL after reacing TIME-WAIT
+                // state, and closed ones nobody holds a handle to anymore
+                self.quad_to_socket.lock().unwrap().retain(|_, socket| {
+                    let mut guard = socket.lock().unwrap();
+                    let expired = guard.tick();
+                    let orphaned = guard.is_closed() && Arc::strong_count(socket) == 1;
+                    !(expired || orphaned)
+                });
+            }
+
+            if !ready {
                 continue;
             }
 
@@ -172,10 +221,27 @@ impl TunDevice {
                                     ),
                                     SocketAddrV4::new(ip.source_addr(), tcp.source_port()),
                                 );
-                                if let Some(socket) =
-                                    self.quad_to_socket.lock().unwrap().get_mut(&quad)
-                                {
+                                let mut quad_to_socket = self.quad_to_socket.lock().unwrap();
+                                let listeners = self.listeners.lock().unwrap();
+                                let listener = listeners.get(&quad.0.port());
+
+                                if let Some(socket) = quad_to_socket.get_mut(&quad) {
                                     socket.lock().unwrap().on_packet(tcp);
+
+                                    if let Some(listener) = listener {
+                                        listener.lock().unwrap().on_packet(quad, socket);
+                                    }
+                                } else if let Some(listener) = listener {
+                                    if let Some(socket) = listener.lock().unwrap().on_segment(
+                                        quad,
+                                        tcp,
+                                        self.tx.clone(),
+                                        Arc::clone(&self.challenge_acks),
+                                        self.isn.as_ref(),
+                                        self.mtu,
+                                    ) {
+                                        quad_to_socket.insert(quad, socket);
+                                    }
                                 } else {
                                     warn!("Received TCP packet for unknown quad: {quad:?}");
                                 }
@@ -199,10 +265,41 @@ impl TunDevice {
         }
     }
 
+    /// opens a connection to `remote_addr`, blocking until the handshake completes
     pub fn connect(
         &self,
         remote_addr: SocketAddrV4,
     ) -> Result<tcp::TcpSocketWrapper, std::io::Error> {
+        let (quad, socket) = self.create_socket(remote_addr);
+
+        match socket.connect() {
+            Ok(()) => Ok(socket),
+            Err(e) => {
+                self.quad_to_socket.lock().unwrap().remove(&quad);
+                Err(e)
+            }
+        }
+    }
+
+    /// like `connect`, but gives up with `TimedOut` after `timeout`
+    pub fn connect_timeout(
+        &self,
+        remote_addr: SocketAddrV4,
+        timeout: Duration,
+    ) -> Result<tcp::TcpSocketWrapper, std::io::Error> {
+        let (quad, socket) = self.create_socket(remote_addr);
+
+        match socket.connect_timeout(timeout) {
+            Ok(()) => Ok(socket),
+            Err(e) => {
+                self.quad_to_socket.lock().unwrap().remove(&quad);
+                Err(e)
+            }
+        }
+    }
+
+    /// creates a socket on a free local port and registers it in the socket table
+    fn create_socket(&self, remote_addr: SocketAddrV4) -> (tcp::Quad, tcp::TcpSocketWrapper) {
         let [a, b, c, d] = self.ip;
         let mut local_addr =
             SocketAddrV4::new(Ipv4Addr::new(a, b, c, d), rand::random_range(10000..=65535));
@@ -220,15 +317,39 @@ impl TunDevice {
             break;
         }
 
-        let socket = tcp::TcpSocket::new(local_addr, remote_addr, self.tx.clone());
+        let quad = (local_addr, remote_addr);
+        let socket = tcp::TcpSocket::new(
+            local_addr,
+            remote_addr,
+            self.isn.isn(&quad),
+            self.tx.clone(),
+            Arc::clone(&self.challenge_acks),
+            self.mtu,
+        );
         let condvar = socket.state_condvar();
         let socket = Arc::new(Mutex::new(socket));
-        quad_to_socket.insert((local_addr, remote_addr), socket.clone());
-        drop(quad_to_socket);
+        quad_to_socket.insert(quad, socket.clone());
+
+        (quad, tcp::TcpSocketWrapper::new(socket, condvar))
+    }
+
+    pub fn listen(&self, port: u16) -> Result<tcp::TcpListener, std::io::Error> {
+        let [a, b, c, d] = self.ip;
+        let local_addr = SocketAddrV4::new(Ipv4Addr::new(a, b, c, d), port);
+
+        let mut listeners = self.listeners.lock().unwrap();
+        if listeners.contains_key(&port) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!("port {port} is already being listened on"),
+            ));
+        }
 
-        let socket = tcp::TcpSocketWrapper::new(socket, condvar);
-        socket.connect();
+        let queue = tcp::ListenQueue::new();
+        let condvar = queue.state_condvar();
+        let queue = Arc::new(Mutex::new(queue));
+        listeners.insert(port, queue.clone());
 
-        Ok(socket)
+        Ok(tcp::TcpListener::new(local_addr, queue, condvar))
     }
 }